@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use clap::Args;
+use ring::rand::{SecureRandom, SystemRandom};
+
+#[derive(Args)]
+#[group(id = "access_log")]
+pub(crate) struct Config {
+    /// Fraction of the requests logged in the access log, from 0 (none) to 1 (all)
+    #[arg(env, long, default_value_t = 1.0, value_parser = parse_sample_rate)]
+    access_log_sample_rate: f64,
+
+    /// Route paths not logged in the access log (e.g. `/health`)
+    #[arg(env, long, value_delimiter = ',')]
+    access_log_exclude: Vec<String>,
+}
+
+fn parse_sample_rate(value: &str) -> Result<f64, String> {
+    let rate = value.parse::<f64>().map_err(|err| err.to_string())?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err("must be between 0 and 1".into());
+    }
+    Ok(rate)
+}
+
+/// Requests recorded in the access log.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLog {
+    sample_rate: f64,
+    /// Route paths (e.g. `/config/{collection}`) of the requests not recorded.
+    excluded: Arc<[String]>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self {
+            sample_rate: 1.0,
+            excluded: Arc::new([]),
+        }
+    }
+}
+
+impl From<&Config> for AccessLog {
+    fn from(config: &Config) -> Self {
+        Self::new(config.access_log_sample_rate, &config.access_log_exclude)
+    }
+}
+
+impl AccessLog {
+    pub(crate) fn new(sample_rate: f64, excluded: &[String]) -> Self {
+        Self {
+            sample_rate,
+            excluded: excluded.into(),
+        }
+    }
+
+    /// Decides whether a request to a route is recorded, randomly according to the sample rate.
+    pub(crate) fn samples(&self, route: Option<&str>) -> bool {
+        if route.is_some_and(|route| self.excluded.iter().any(|excluded| excluded == route)) {
+            return false;
+        }
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        let mut bytes = [0; 8];
+        if SystemRandom::new().fill(&mut bytes).is_err() {
+            return true;
+        }
+        // Uniform in [0, 1), from the 53 bits of an `f64` mantissa.
+        let draw = (u64::from_le_bytes(bytes) >> 11) as f64 / (1_u64 << 53) as f64;
+        draw < self.sample_rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples() {
+        let all = AccessLog::new(1.0, &["/health".into()]);
+        assert!(all.samples(Some("/config/{collection}")));
+        assert!(all.samples(None));
+        assert!(!all.samples(Some("/health")));
+        let none = AccessLog::new(0.0, &[]);
+        assert!(!none.samples(Some("/config/{collection}")));
+        let half = AccessLog::new(0.5, &[]);
+        let sampled = (0..1000).filter(|_| half.samples(None)).count();
+        assert!((300..700).contains(&sampled), "{sampled}");
+    }
+
+    #[test]
+    fn sample_rate() {
+        assert_eq!(parse_sample_rate("0.25"), Ok(0.25));
+        assert!(parse_sample_rate("1.5").is_err());
+        assert!(parse_sample_rate("often").is_err());
+    }
+}
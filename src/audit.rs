@@ -0,0 +1,215 @@
+use mongodb::bson::{Bson, DateTime, Document, doc};
+
+use crate::diff::diff_documents;
+use crate::links::DocumentLocation;
+use crate::patch::{JsonPointer, Patch, PatchOperation};
+
+/// Who makes a write, and within which request.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct WriteContext {
+    /// Identity of the authenticated caller, if any.
+    pub(crate) caller: Option<String>,
+    /// Identity the caller declares without being authenticated, if any.
+    pub(crate) claimed_caller: Option<String>,
+    /// Roles or groups of the caller, granting additional authorizations.
+    pub(crate) roles: Vec<String>,
+    pub(crate) request_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum WriteOperation {
+    Patch,
+    Delete,
+    PutSchema,
+}
+
+impl WriteOperation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Patch => "patch",
+            Self::Delete => "delete",
+            Self::PutSchema => "putSchema",
+        }
+    }
+}
+
+/// Change of a document, to be recorded in the history collection.
+#[derive(Debug)]
+pub(crate) struct HistoryEntry<'a> {
+    pub(crate) location: &'a DocumentLocation,
+    pub(crate) operation: WriteOperation,
+    /// Document before the write, if it existed.
+    pub(crate) before: Option<&'a Document>,
+    /// Document after the write, if it still exists.
+    pub(crate) after: Option<&'a Document>,
+    pub(crate) context: &'a WriteContext,
+}
+
+impl HistoryEntry<'_> {
+    pub(crate) fn to_document(&self, revision: i64, timestamp: DateTime) -> Document {
+        let empty = Document::new();
+        let changes = diff_documents(self.before.unwrap_or(&empty), self.after.unwrap_or(&empty));
+        doc! {
+            "collection": &self.location.collection,
+            "id": self.location.id.clone(),
+            "revision": revision,
+            "operation": self.operation.as_str(),
+            "changes": changes,
+            "document": self.after.cloned().map_or(Bson::Null, Bson::Document),
+            "caller": self.context.caller.clone(),
+            "claimedCaller": self.context.claimed_caller.clone(),
+            "requestId": &self.context.request_id,
+            "timestamp": timestamp,
+        }
+    }
+}
+
+/// Formats a history entry as a revision of its document, without the document itself.
+pub(crate) fn revision_summary(entry: &Document) -> Document {
+    let mut summary = entry.clone();
+    for key in ["_id", "collection", "id", "document"] {
+        summary.remove(key);
+    }
+    if let Ok(timestamp) = entry.get_datetime("timestamp")
+        && let Ok(timestamp) = timestamp.try_to_rfc3339_string()
+    {
+        summary.insert("timestamp", timestamp);
+    }
+    summary
+}
+
+/// Builds the JSON Patch turning the current version of a document back into a previous one.
+///
+/// The changed values are tested first, so that the patch fails if they are concurrently modified.
+pub(crate) fn restore_patch(current: &Document, previous: &Document) -> Patch {
+    let mut operations = Vec::new();
+    for change in diff_documents(current, previous) {
+        let path = JsonPointer::try_from(change.path).expect("diff paths are valid pointers");
+        if let Some(value) = change.old {
+            operations.push(PatchOperation::Test {
+                path: path.clone(),
+                value,
+            });
+        }
+        operations.push(match change.new {
+            Some(value) => PatchOperation::Add { path, value },
+            None => PatchOperation::Remove { path },
+        });
+    }
+    Patch::Json(operations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_document() {
+        let location = DocumentLocation::new("coll", "a");
+        let context = WriteContext {
+            caller: None,
+            claimed_caller: Some("someone".into()),
+            roles: Vec::new(),
+            request_id: "req".into(),
+        };
+        let before = doc! { "_id": "a", "b": 1, "c": 2 };
+        let after = doc! { "_id": "a", "b": 3, "c": 2 };
+        let entry = HistoryEntry {
+            location: &location,
+            operation: WriteOperation::Patch,
+            before: Some(&before),
+            after: Some(&after),
+            context: &context,
+        };
+        let timestamp = DateTime::from_millis(0);
+        let expected = doc! {
+            "collection": "coll",
+            "id": "a",
+            "revision": 2_i64,
+            "operation": "patch",
+            "changes": [{ "path": "/b", "old": 1, "new": 3 }],
+            "document": { "_id": "a", "b": 3, "c": 2 },
+            "caller": null,
+            "claimedCaller": "someone",
+            "requestId": "req",
+            "timestamp": timestamp,
+        };
+        assert_eq!(entry.to_document(2, timestamp), expected);
+    }
+
+    #[test]
+    fn deletion() {
+        let location = DocumentLocation::new("coll", "a");
+        let context = WriteContext::default();
+        let before = doc! { "_id": "a", "b": 1 };
+        let entry = HistoryEntry {
+            location: &location,
+            operation: WriteOperation::Delete,
+            before: Some(&before),
+            after: None,
+            context: &context,
+        };
+        let document = entry.to_document(1, DateTime::from_millis(0));
+        assert_eq!(document.get("document"), Some(&Bson::Null));
+        assert_eq!(document.get("caller"), Some(&Bson::Null));
+        assert_eq!(
+            document.get_array("changes").unwrap(),
+            &vec![
+                Bson::from(doc! { "path": "/_id", "old": "a" }),
+                Bson::from(doc! { "path": "/b", "old": 1 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let entry = doc! {
+            "_id": 1,
+            "collection": "coll",
+            "id": "a",
+            "revision": 1_i64,
+            "operation": "patch",
+            "changes": [],
+            "document": { "_id": "a" },
+            "timestamp": DateTime::from_millis(0),
+        };
+        let expected = doc! {
+            "revision": 1_i64,
+            "operation": "patch",
+            "changes": [],
+            "timestamp": "1970-01-01T00:00:00Z",
+        };
+        assert_eq!(revision_summary(&entry), expected);
+    }
+
+    #[test]
+    fn restore() {
+        let current = doc! { "_id": "a", "b": 2, "c": { "d": 1 }, "e": true };
+        let previous = doc! { "_id": "a", "b": 1, "c": { "d": 1, "f": "x" } };
+        let Patch::Json(operations) = restore_patch(&current, &previous) else {
+            panic!("restore patch is a JSON Patch");
+        };
+        let expected = [
+            PatchOperation::Test {
+                path: JsonPointer::new(vec!["b".into()]),
+                value: 2.into(),
+            },
+            PatchOperation::Add {
+                path: JsonPointer::new(vec!["b".into()]),
+                value: 1.into(),
+            },
+            PatchOperation::Add {
+                path: JsonPointer::new(vec!["c".into(), "f".into()]),
+                value: "x".into(),
+            },
+            PatchOperation::Test {
+                path: JsonPointer::new(vec!["e".into()]),
+                value: true.into(),
+            },
+            PatchOperation::Remove {
+                path: JsonPointer::new(vec!["e".into()]),
+            },
+        ];
+        assert_eq!(operations, expected);
+    }
+}
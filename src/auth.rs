@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::Context;
+use clap::Args;
+use mongodb::bson::deserialize_from_document;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, instrument};
+
+use crate::db::{FindApiKeyChannel, FindApiKeyRequest};
+use crate::jwt::{Claims, JwtValidator};
+
+#[derive(Args)]
+#[group(id = "auth")]
+pub(crate) struct Config {
+    /// JSON file holding the API keys, enabling authentication
+    #[arg(env, long, conflicts_with = "api_keys_collection")]
+    api_keys_file: Option<PathBuf>,
+
+    /// Collection holding the API keys, enabling authentication
+    #[arg(env, long)]
+    api_keys_collection: Option<String>,
+
+    /// JSON file mapping client certificate subjects to principals, enabling authentication
+    #[arg(env, long)]
+    client_certificates_file: Option<PathBuf>,
+}
+
+impl Config {
+    pub(crate) fn api_keys_collection(&self) -> Option<&str> {
+        self.api_keys_collection.as_deref()
+    }
+}
+
+/// Kind of access granted by an API key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Scope {
+    Read,
+    Write,
+    /// Access to the admin routes.
+    Admin,
+}
+
+impl Scope {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Self::Read, Self::Write, Self::Admin]
+            .into_iter()
+            .find(|scope| scope.as_str() == name)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct ApiKey {
+    name: String,
+    /// Hexadecimal SHA-256 hash of the key.
+    hash: String,
+    scopes: Vec<Scope>,
+    /// Collections the key gives access to, all of them if absent.
+    collections: Option<Vec<String>>,
+    /// Roles granting additional authorizations.
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Principal of the callers giving a client certificate with a given subject.
+#[derive(Debug, Clone, Deserialize)]
+struct CertificatePrincipal {
+    /// Subject distinguished name, as formatted by RFC 4514.
+    subject: String,
+    name: String,
+    scopes: Vec<Scope>,
+    collections: Option<Vec<String>>,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+/// Authenticated caller.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Identity {
+    pub(crate) name: String,
+    scopes: Vec<Scope>,
+    collections: Option<Vec<String>>,
+    pub(crate) roles: Vec<String>,
+    /// Verified claims, if authenticated by a JWT.
+    pub(crate) claims: Option<Claims>,
+}
+
+impl From<ApiKey> for Identity {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            name: key.name,
+            scopes: key.scopes,
+            collections: key.collections,
+            roles: key.roles,
+            claims: None,
+        }
+    }
+}
+
+impl From<CertificatePrincipal> for Identity {
+    fn from(principal: CertificatePrincipal) -> Self {
+        Self {
+            name: principal.name,
+            scopes: principal.scopes,
+            collections: principal.collections,
+            roles: principal.roles,
+            claims: None,
+        }
+    }
+}
+
+impl Identity {
+    /// Collections the key gives access to, all of them if `None`.
+    pub(crate) fn collections(&self) -> Option<&[String]> {
+        self.collections.as_deref()
+    }
+
+    /// Checks that the key gives access to a collection.
+    pub(crate) fn authorize_collection(&self, collection: &str) -> Result<(), AuthError> {
+        let allowed = self
+            .collections
+            .as_ref()
+            .is_none_or(|collections| collections.iter().any(|allowed| allowed == collection));
+        if !allowed {
+            return Err(AuthError::Forbidden(format!(
+                "`{}` key has no access to `{collection}` collection",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    fn authorize(&self, collection: Option<&str>, scope: Scope) -> Result<(), AuthError> {
+        if !self.scopes.contains(&scope) {
+            return Err(AuthError::Forbidden(format!(
+                "`{}` key has no {} scope",
+                self.name,
+                scope.as_str()
+            )));
+        }
+        match collection {
+            Some(collection) => self.authorize_collection(collection),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum AuthError {
+    /// No key nor token has been given.
+    Missing,
+    /// The given key is unknown.
+    Invalid,
+    /// The given token is not valid.
+    InvalidToken(String),
+    /// No principal is mapped to the subject of the client certificate.
+    UnknownCertificate(String),
+    /// The key does not grant the requested access.
+    Forbidden(String),
+    /// The keys could not be looked up.
+    Internal,
+}
+
+/// Credentials given by a caller.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Credentials<'a> {
+    ApiKey(&'a str),
+    Bearer(&'a str),
+    /// Subject of a verified client certificate.
+    Certificate(&'a str),
+}
+
+/// Source of the API keys, if enabled.
+#[derive(Clone, Default)]
+pub(crate) enum ApiKeys {
+    #[default]
+    Disabled,
+    /// Keys loaded from a file, by hash.
+    File(Arc<HashMap<String, ApiKey>>),
+    /// Keys looked up in a collection on each request.
+    Collection {
+        collection: String,
+        channel: FindApiKeyChannel,
+    },
+}
+
+/// Returns the hexadecimal SHA-256 hash of an API key, as stored.
+pub(crate) fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+impl ApiKeys {
+    #[instrument(skip_all)]
+    async fn create(config: &Config, channel: FindApiKeyChannel) -> anyhow::Result<Self> {
+        if let Some(path) = &config.api_keys_file {
+            let content = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("error reading API keys file `{}`", path.display()))?;
+            let keys = serde_json::from_slice::<Vec<ApiKey>>(&content)
+                .with_context(|| format!("error parsing API keys file `{}`", path.display()))?;
+            info!(msg = "API keys loaded", count = keys.len());
+            return Ok(Self::from_keys(keys));
+        }
+        if let Some(collection) = &config.api_keys_collection {
+            info!(msg = "API keys looked up", collection);
+            return Ok(Self::Collection {
+                collection: collection.clone(),
+                channel,
+            });
+        }
+        Ok(Self::Disabled)
+    }
+
+    fn from_keys(keys: Vec<ApiKey>) -> Self {
+        let keys = keys
+            .into_iter()
+            .map(|key| (key.hash.to_lowercase(), key))
+            .collect();
+        Self::File(Arc::new(keys))
+    }
+
+    async fn find(&self, key: &str) -> Result<Option<Identity>, AuthError> {
+        let hash = hash_key(key);
+        match self {
+            Self::Disabled => Ok(None),
+            Self::File(keys) => Ok(keys.get(&hash).cloned().map(Identity::from)),
+            Self::Collection {
+                collection,
+                channel,
+            } => {
+                let request = FindApiKeyRequest {
+                    collection: collection.clone(),
+                    hash,
+                };
+                let found = channel.roundtrip(request).await.map_err(|err| {
+                    error!(kind = "API key find channel roundtrip", %err);
+                    AuthError::Internal
+                })?;
+                let key = found
+                    .map(deserialize_from_document::<ApiKey>)
+                    .transpose()
+                    .map_err(|err| {
+                        error!(kind = "API key document", %err);
+                        AuthError::Internal
+                    })?;
+                Ok(key.map(Identity::from))
+            }
+        }
+    }
+}
+
+/// Ways of authenticating callers, authentication being disabled if there is none.
+#[derive(Clone, Default)]
+pub(crate) struct Authentication {
+    pub(crate) api_keys: ApiKeys,
+    pub(crate) jwt: Option<Arc<JwtValidator>>,
+    /// Identities of the client certificates, by subject.
+    pub(crate) certificates: Option<Arc<HashMap<String, Identity>>>,
+}
+
+#[instrument(skip_all)]
+async fn load_certificate_principals(
+    config: &Config,
+) -> anyhow::Result<Option<HashMap<String, Identity>>> {
+    let Some(path) = &config.client_certificates_file else {
+        return Ok(None);
+    };
+    let content = tokio::fs::read(path).await.with_context(|| {
+        format!(
+            "error reading client certificates file `{}`",
+            path.display()
+        )
+    })?;
+    let principals =
+        serde_json::from_slice::<Vec<CertificatePrincipal>>(&content).with_context(|| {
+            format!(
+                "error parsing client certificates file `{}`",
+                path.display()
+            )
+        })?;
+    info!(
+        msg = "client certificate principals loaded",
+        count = principals.len()
+    );
+    Ok(Some(
+        principals
+            .into_iter()
+            .map(|principal| (principal.subject.clone(), Identity::from(principal)))
+            .collect(),
+    ))
+}
+
+impl Authentication {
+    #[instrument(skip_all)]
+    pub(crate) async fn create(
+        config: &Config,
+        jwt_config: &crate::jwt::Config,
+        channel: FindApiKeyChannel,
+    ) -> anyhow::Result<Self> {
+        let authentication = Self {
+            api_keys: ApiKeys::create(config, channel).await?,
+            jwt: JwtValidator::create(jwt_config).await?.map(Arc::new),
+            certificates: load_certificate_principals(config).await?.map(Arc::new),
+        };
+        if authentication.is_disabled() {
+            info!(msg = "authentication disabled");
+        }
+        Ok(authentication)
+    }
+
+    pub(crate) fn is_disabled(&self) -> bool {
+        matches!(self.api_keys, ApiKeys::Disabled)
+            && self.jwt.is_none()
+            && self.certificates.is_none()
+    }
+
+    /// Checks that credentials grant some access to a collection (if any), returning the identity
+    /// of their owner unless authentication is disabled.
+    pub(crate) async fn authenticate(
+        &self,
+        credentials: Option<Credentials<'_>>,
+        collection: Option<&str>,
+        scope: Scope,
+    ) -> Result<Option<Identity>, AuthError> {
+        if self.is_disabled() {
+            return Ok(None);
+        }
+        let identity = match credentials {
+            None => return Err(AuthError::Missing),
+            Some(Credentials::ApiKey(key)) => {
+                self.api_keys.find(key).await?.ok_or(AuthError::Invalid)?
+            }
+            Some(Credentials::Bearer(token)) => {
+                let Some(jwt) = &self.jwt else {
+                    return Err(AuthError::InvalidToken(
+                        "bearer tokens are not accepted".into(),
+                    ));
+                };
+                let claims = jwt
+                    .validate(token)
+                    .map_err(|err| AuthError::InvalidToken(err.to_string()))?;
+                let Some(subject) = claims.get("sub").and_then(|sub| sub.as_str()) else {
+                    return Err(AuthError::InvalidToken("missing `sub` claim".into()));
+                };
+                Identity {
+                    name: subject.to_owned(),
+                    // Other scopes may be granted to the token for other services.
+                    scopes: jwt
+                        .scopes(&claims)
+                        .iter()
+                        .filter_map(|scope| Scope::from_name(scope))
+                        .collect(),
+                    collections: None,
+                    roles: jwt.roles(&claims),
+                    claims: Some(claims),
+                }
+            }
+            Some(Credentials::Certificate(subject)) => {
+                let Some(certificates) = &self.certificates else {
+                    return Err(AuthError::Missing);
+                };
+                certificates
+                    .get(subject)
+                    .cloned()
+                    .ok_or_else(|| AuthError::UnknownCertificate(subject.to_owned()))?
+            }
+        };
+        identity.authorize(collection, scope)?;
+        Ok(Some(identity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authentication() -> Authentication {
+        let api_keys = ApiKeys::from_keys(vec![
+            ApiKey {
+                name: "reader".into(),
+                hash: hash_key("reader-key"),
+                scopes: vec![Scope::Read],
+                collections: Some(vec!["machines".into()]),
+                roles: Vec::new(),
+            },
+            ApiKey {
+                name: "admin".into(),
+                hash: hash_key("admin-key").to_uppercase(),
+                scopes: vec![Scope::Read, Scope::Write],
+                collections: None,
+                roles: vec!["maintenance".into()],
+            },
+        ]);
+        let certificates = HashMap::from([(
+            "CN=robot,O=Plant".to_owned(),
+            Identity {
+                name: "robot".into(),
+                scopes: vec![Scope::Read],
+                collections: None,
+                roles: vec!["robots".into()],
+                claims: None,
+            },
+        )]);
+        Authentication {
+            api_keys,
+            jwt: None,
+            certificates: Some(Arc::new(certificates)),
+        }
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(
+            hash_key("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        let result = Authentication::default()
+            .authenticate(None, Some("machines"), Scope::Write)
+            .await;
+        assert_eq!(result, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn missing_key() {
+        let result = authentication()
+            .authenticate(None, Some("machines"), Scope::Read)
+            .await;
+        assert_eq!(result, Err(AuthError::Missing));
+    }
+
+    #[tokio::test]
+    async fn invalid_key() {
+        let result = authentication()
+            .authenticate(
+                Some(Credentials::ApiKey("unknown")),
+                Some("machines"),
+                Scope::Read,
+            )
+            .await;
+        assert_eq!(result, Err(AuthError::Invalid));
+    }
+
+    #[tokio::test]
+    async fn granted() {
+        let identity = authentication()
+            .authenticate(
+                Some(Credentials::ApiKey("reader-key")),
+                Some("machines"),
+                Scope::Read,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name, "reader");
+        let identity = authentication()
+            .authenticate(
+                Some(Credentials::ApiKey("admin-key")),
+                Some("plants"),
+                Scope::Write,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name, "admin");
+        assert_eq!(identity.roles, ["maintenance"]);
+    }
+
+    #[tokio::test]
+    async fn forbidden() {
+        let result = authentication()
+            .authenticate(
+                Some(Credentials::ApiKey("reader-key")),
+                Some("machines"),
+                Scope::Write,
+            )
+            .await;
+        assert!(matches!(result, Err(AuthError::Forbidden(_))));
+        let result = authentication()
+            .authenticate(
+                Some(Credentials::ApiKey("reader-key")),
+                Some("plants"),
+                Scope::Read,
+            )
+            .await;
+        assert!(matches!(result, Err(AuthError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn bearer_not_accepted() {
+        let result = authentication()
+            .authenticate(Some(Credentials::Bearer("token")), None, Scope::Read)
+            .await;
+        assert!(matches!(result, Err(AuthError::InvalidToken(_))));
+    }
+
+    #[tokio::test]
+    async fn certificate() {
+        let identity = authentication()
+            .authenticate(
+                Some(Credentials::Certificate("CN=robot,O=Plant")),
+                Some("machines"),
+                Scope::Read,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(identity.name, "robot");
+        assert_eq!(identity.roles, ["robots"]);
+        let result = authentication()
+            .authenticate(
+                Some(Credentials::Certificate("CN=intruder")),
+                Some("machines"),
+                Scope::Read,
+            )
+            .await;
+        assert!(matches!(result, Err(AuthError::UnknownCertificate(_))));
+        let result = Authentication {
+            certificates: None,
+            ..authentication()
+        }
+        .authenticate(
+            Some(Credentials::Certificate("CN=robot,O=Plant")),
+            Some("machines"),
+            Scope::Read,
+        )
+        .await;
+        assert_eq!(result, Err(AuthError::Missing));
+    }
+}
@@ -0,0 +1,462 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use clap::Args;
+use mongodb::bson::{self, Bson, Document, deserialize_from_bson};
+use serde::Deserialize;
+
+use crate::audit::WriteContext;
+use crate::exposure::Exposure;
+use crate::patch::JsonPointer;
+
+#[derive(Args)]
+#[group(id = "authorization")]
+pub(crate) struct Config {
+    /// Collections callers can access, all of them if not set
+    #[arg(env, long, value_delimiter = ',')]
+    readable_collections: Vec<String>,
+
+    /// Collections callers can not access
+    #[arg(env, long, value_delimiter = ',')]
+    hidden_collections: Vec<String>,
+}
+
+/// Collections callers can access, whatever their other authorizations.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CollectionFilter {
+    /// Allowed collections, all of them if empty.
+    readable: Arc<[String]>,
+    hidden: Arc<[String]>,
+    /// Collections used by the service itself, never accessible.
+    internal: Arc<[String]>,
+}
+
+impl From<&Config> for CollectionFilter {
+    fn from(config: &Config) -> Self {
+        Self::new(&config.readable_collections, &config.hidden_collections)
+    }
+}
+
+impl CollectionFilter {
+    pub(crate) fn new(readable: &[String], hidden: &[String]) -> Self {
+        Self {
+            readable: readable.into(),
+            hidden: hidden.into(),
+            internal: Arc::default(),
+        }
+    }
+
+    /// Refuses the collections used by the service itself, whatever the other settings.
+    pub(crate) fn with_internal(self, internal: Vec<String>) -> Self {
+        Self {
+            internal: internal.into(),
+            ..self
+        }
+    }
+
+    pub(crate) fn is_readable(&self, collection: &str) -> bool {
+        (self.readable.is_empty() || self.readable.iter().any(|readable| readable == collection))
+            && !self.hidden.iter().any(|hidden| hidden == collection)
+            && !self.internal.iter().any(|internal| internal == collection)
+    }
+}
+
+/// Collections a request may read while following links, layers and placeholders.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReadAccess {
+    filter: Arc<CollectionFilter>,
+    exposure: Exposure,
+    /// Collections granted to the caller, all of them if absent.
+    collections: Option<Vec<String>>,
+}
+
+impl ReadAccess {
+    pub(crate) fn new(
+        filter: Arc<CollectionFilter>,
+        exposure: Exposure,
+        collections: Option<Vec<String>>,
+    ) -> Self {
+        Self {
+            filter,
+            exposure,
+            collections,
+        }
+    }
+
+    /// Checks that a collection can be read, both by any caller and by this one.
+    pub(crate) fn allows(&self, collection: &str) -> bool {
+        self.is_readable(collection) && self.is_granted(collection)
+    }
+
+    /// Checks that a collection is exposed and can be read by any caller, whatever the ones
+    /// granted to this one.
+    pub(crate) fn is_readable(&self, collection: &str) -> bool {
+        self.exposure.is_exposed(collection) && self.filter.is_readable(collection)
+    }
+
+    fn is_granted(&self, collection: &str) -> bool {
+        self.collections
+            .as_ref()
+            .is_none_or(|collections| collections.iter().any(|granted| granted == collection))
+    }
+}
+
+/// Top-level fields of a collection documents callers can read, as set by the `readableFields`
+/// and `hiddenFields` arrays of its `_authorization` document.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct FieldFilter {
+    /// Readable fields, all of them if absent.
+    readable: Option<Vec<String>>,
+    hidden: Vec<String>,
+}
+
+impl FieldFilter {
+    pub(crate) fn from_authorization(authorization: Option<&Document>) -> Self {
+        let strings = |field| {
+            authorization?.get_array(field).ok().map(|values| {
+                values
+                    .iter()
+                    .filter_map(Bson::as_str)
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+        };
+        Self {
+            readable: strings("readableFields"),
+            hidden: strings("hiddenFields").unwrap_or_default(),
+        }
+    }
+
+    /// Checks that a top-level field can be read, the ID always being readable.
+    fn is_readable(&self, field: &str) -> bool {
+        field == "_id"
+            || (self
+                .readable
+                .as_ref()
+                .is_none_or(|readable| readable.iter().any(|allowed| allowed == field))
+                && !self.hidden.iter().any(|hidden| hidden == field))
+    }
+
+    /// Removes the top-level fields which can not be read from a document.
+    pub(crate) fn redact(&self, document: &mut Document) {
+        let redacted = document
+            .keys()
+            .filter(|field| !self.is_readable(field))
+            .cloned()
+            .collect::<Vec<_>>();
+        for field in redacted {
+            document.remove(field);
+        }
+    }
+
+    /// Checks that the whole value at a JSON pointer can be read, the root pointer referring to
+    /// all the fields.
+    pub(crate) fn is_wholly_readable(&self, pointer: &JsonPointer) -> bool {
+        match pointer.top_level() {
+            "" => self.readable.is_none() && self.hidden.is_empty(),
+            field => self.is_readable(field),
+        }
+    }
+
+    /// Checks that the value at a JSON pointer can be read.
+    pub(crate) fn is_readable_path(&self, path: &str) -> bool {
+        JsonPointer::try_from(path.to_owned()).is_ok_and(|pointer| {
+            pointer.top_level().is_empty() || self.is_readable(pointer.top_level())
+        })
+    }
+}
+
+/// Write operation granted or denied by rules.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RuleOperation {
+    Patch,
+    /// Schema update.
+    Put,
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Effect {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Rule of the `_authorization` document `rules` array.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    #[serde(default)]
+    effect: Effect,
+    /// Name of the authenticated caller the rule applies to.
+    principal: Option<String>,
+    /// Role of the caller the rule applies to.
+    role: Option<String>,
+    operations: Vec<RuleOperation>,
+    /// Top-level fields the rule applies to on patches, all of them if absent.
+    fields: Option<Vec<String>>,
+    /// Patterns of the document IDs the rule applies to (`*` matching any characters), all of
+    /// them if absent.
+    ids: Option<Vec<String>>,
+}
+
+/// Matches a string against a pattern in which `*` matches any sequence of characters.
+pub(crate) fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl Rule {
+    fn applies(&self, context: &WriteContext, id: &str, operation: RuleOperation) -> bool {
+        let principal = self
+            .principal
+            .as_ref()
+            .is_none_or(|principal| context.caller.as_ref() == Some(principal));
+        let role = self
+            .role
+            .as_ref()
+            .is_none_or(|role| context.roles.contains(role));
+        let id = self
+            .ids
+            .as_ref()
+            .is_none_or(|patterns| patterns.iter().any(|pattern| matches_pattern(pattern, id)));
+        principal && role && id && self.operations.contains(&operation)
+    }
+
+    fn covers(&self, field: &str) -> bool {
+        self.fields
+            .as_ref()
+            .is_none_or(|fields| fields.iter().any(|allowed| allowed == field))
+    }
+}
+
+/// Authorization rules of a collection, deny rules taking precedence over allow ones.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Rules(Vec<Rule>);
+
+impl Rules {
+    /// Reads the rules of an `_authorization` document, if it has any.
+    pub(crate) fn from_authorization(
+        authorization: &Document,
+    ) -> Result<Option<Self>, bson::error::Error> {
+        authorization
+            .get("rules")
+            .cloned()
+            .map(deserialize_from_bson::<Vec<Rule>>)
+            .transpose()
+            .map(|rules| rules.map(Self))
+    }
+
+    fn applicable<'a>(
+        &'a self,
+        context: &'a WriteContext,
+        id: &'a str,
+        operation: RuleOperation,
+    ) -> impl Iterator<Item = &'a Rule> {
+        self.0
+            .iter()
+            .filter(move |rule| rule.applies(context, id, operation))
+    }
+
+    /// Lists the fields a patch of a document is not allowed to change.
+    pub(crate) fn denied_fields<'a>(
+        &self,
+        context: &WriteContext,
+        id: &str,
+        fields: impl IntoIterator<Item = &'a str>,
+    ) -> BTreeSet<String> {
+        let rules = self
+            .applicable(context, id, RuleOperation::Patch)
+            .collect::<Vec<_>>();
+        fields
+            .into_iter()
+            .filter(|field| {
+                let mut covering = rules.iter().filter(|rule| rule.covers(field));
+                !covering.clone().any(|rule| rule.effect == Effect::Allow)
+                    || covering.any(|rule| rule.effect == Effect::Deny)
+            })
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Checks that a deletion or a schema update is allowed.
+    pub(crate) fn allows(
+        &self,
+        context: &WriteContext,
+        id: &str,
+        operation: RuleOperation,
+    ) -> bool {
+        let mut allowed = false;
+        for rule in self.applicable(context, id, operation) {
+            match rule.effect {
+                Effect::Allow => allowed = true,
+                // Field-level deny rules only restrict patches.
+                Effect::Deny if rule.fields.is_none() => return false,
+                Effect::Deny => {}
+            }
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    fn rules() -> Rules {
+        let authorization = doc! {
+            "_id": "_authorization",
+            "rules": [
+                { "operations": ["patch"], "fields": ["comment"] },
+                { "role": "maintenance", "operations": ["patch", "delete"] },
+                {
+                    "effect": "deny",
+                    "role": "maintenance",
+                    "operations": ["patch"],
+                    "fields": ["limits"],
+                    "ids": ["line-*"],
+                },
+                { "principal": "admin", "operations": ["put", "delete"] },
+                { "effect": "deny", "operations": ["delete"], "ids": ["*-prod"] },
+            ],
+        };
+        Rules::from_authorization(&authorization).unwrap().unwrap()
+    }
+
+    fn context(caller: &str, roles: &[&str], authenticated: bool) -> WriteContext {
+        let caller = Some(caller.into());
+        let (caller, claimed_caller) = if authenticated {
+            (caller, None)
+        } else {
+            (None, caller)
+        };
+        WriteContext {
+            caller,
+            claimed_caller,
+            roles: roles.iter().map(|role| (*role).to_owned()).collect(),
+            ..WriteContext::default()
+        }
+    }
+
+    #[test]
+    fn collection_filter() {
+        let filter = CollectionFilter::from(&Config {
+            readable_collections: vec!["machines".into(), "secrets".into()],
+            hidden_collections: vec!["secrets".into()],
+        });
+        assert!(filter.is_readable("machines"));
+        assert!(!filter.is_readable("secrets"));
+        assert!(!filter.is_readable("plants"));
+        assert!(CollectionFilter::default().is_readable("plants"));
+        let filter = CollectionFilter::default().with_internal(vec!["history".into()]);
+        assert!(!filter.is_readable("history"));
+        assert!(filter.is_readable("plants"));
+    }
+
+    #[test]
+    fn read_access() {
+        let filter = Arc::new(CollectionFilter::new(&[], &["secrets".into()]));
+        let access = ReadAccess::new(
+            Arc::clone(&filter),
+            Exposure::default(),
+            Some(vec!["machines".into()]),
+        );
+        assert!(access.allows("machines"));
+        assert!(!access.allows("plants"));
+        assert!(access.is_readable("plants"));
+        assert!(!access.is_readable("secrets"));
+        let access = ReadAccess::new(filter, Exposure::new(&["line-*".into()], false), None);
+        assert!(access.allows("line-1"));
+        assert!(!access.allows("plants"));
+        assert!(!access.is_readable("plants"));
+    }
+
+    #[test]
+    fn field_filter() {
+        let authorization = doc! {
+            "_id": "_authorization",
+            "readableFields": ["a", "b"],
+            "hiddenFields": ["b", "c"],
+        };
+        let filter = FieldFilter::from_authorization(Some(&authorization));
+        let mut document = doc! { "_id": 1, "a": { "b": 1 }, "b": 2, "c": 3, "d": 4 };
+        filter.redact(&mut document);
+        assert_eq!(document, doc! { "_id": 1, "a": { "b": 1 } });
+        assert!(filter.is_readable_path("/a/b"));
+        assert!(!filter.is_readable_path("/c"));
+        let pointer = |path: &str| JsonPointer::try_from(path.to_owned()).unwrap();
+        assert!(filter.is_wholly_readable(&pointer("/a/b")));
+        assert!(!filter.is_wholly_readable(&pointer("/c")));
+        assert!(!filter.is_wholly_readable(&pointer("")));
+
+        let filter = FieldFilter::from_authorization(None);
+        let mut document = doc! { "a": 1 };
+        filter.redact(&mut document);
+        assert_eq!(document, doc! { "a": 1 });
+        assert!(filter.is_wholly_readable(&pointer("")));
+    }
+
+    #[test]
+    fn pattern() {
+        assert!(matches_pattern("line-*", "line-1"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("a*b*c", "abbc"));
+        assert!(matches_pattern("exact", "exact"));
+        assert!(!matches_pattern("exact", "exactly"));
+        assert!(!matches_pattern("ab*ba", "aba"));
+        assert!(!matches_pattern("line-*", "other"));
+    }
+
+    #[test]
+    fn no_rules() {
+        let authorization = doc! { "_id": "_authorization", "patchAllowedFields": ["a"] };
+        assert_eq!(Rules::from_authorization(&authorization).unwrap(), None);
+        let authorization = doc! { "_id": "_authorization", "rules": [{ "fields": ["a"] }] };
+        assert!(Rules::from_authorization(&authorization).is_err());
+    }
+
+    #[test]
+    fn denied_fields() {
+        let rules = rules();
+        let everyone = context("someone", &[], false);
+        let denied = rules.denied_fields(&everyone, "line-1", ["comment", "limits"]);
+        assert_eq!(denied, BTreeSet::from(["limits".into()]));
+        let maintenance = context("someone", &["maintenance"], true);
+        let denied = rules.denied_fields(&maintenance, "machine-1", ["comment", "limits"]);
+        assert!(denied.is_empty());
+        let denied = rules.denied_fields(&maintenance, "line-1", ["comment", "limits"]);
+        assert_eq!(denied, BTreeSet::from(["limits".into()]));
+    }
+
+    #[test]
+    fn allows() {
+        let rules = rules();
+        let admin = context("admin", &[], true);
+        assert!(rules.allows(&admin, "_schema", RuleOperation::Put));
+        assert!(rules.allows(&admin, "line-1", RuleOperation::Delete));
+        assert!(!rules.allows(&admin, "line-prod", RuleOperation::Delete));
+        let declared = context("admin", &[], false);
+        assert!(!rules.allows(&declared, "_schema", RuleOperation::Put));
+        let maintenance = context("someone", &["maintenance"], true);
+        assert!(rules.allows(&maintenance, "line-1", RuleOperation::Delete));
+        assert!(!rules.allows(&maintenance, "_schema", RuleOperation::Put));
+    }
+}
@@ -1,8 +1,10 @@
 use std::time::Duration;
 
 use tokio::sync::{mpsc, oneshot};
+use tracing::{Span, info_span};
 
-type RequestPayload<S, R> = (S, oneshot::Sender<R>);
+/// Request, reply sender and span of the sender.
+type RequestPayload<S, R> = (S, oneshot::Sender<R>, Span);
 
 const SEND_TIMEOUT: Duration = Duration::from_millis(100);
 const RECEIVE_TIMEOUT: Duration = Duration::from_millis(500);
@@ -23,7 +25,7 @@ impl<S, R> RoundtripSender<S, R> {
     pub(crate) async fn roundtrip(&self, request: S) -> Result<R, String> {
         let (reply_tx, reply_rx) = oneshot::channel();
         self.inner
-            .send_timeout((request, reply_tx), SEND_TIMEOUT)
+            .send_timeout((request, reply_tx, Span::current()), SEND_TIMEOUT)
             .await
             .map_err(|err| err.to_string())?;
         let reply = tokio::time::timeout(RECEIVE_TIMEOUT, reply_rx)
@@ -34,16 +36,42 @@ impl<S, R> RoundtripSender<S, R> {
     }
 }
 
+pub(crate) struct RoundtripReceiver<S, R> {
+    inner: mpsc::Receiver<RequestPayload<S, R>>,
+}
+
+impl<S, R> RoundtripReceiver<S, R> {
+    #[cfg(test)]
+    pub(crate) async fn recv(&mut self) -> Option<(S, oneshot::Sender<R>)> {
+        let (request, reply_tx, _) = self.inner.recv().await?;
+        Some((request, reply_tx))
+    }
+
+    /// Receives a request with the span to handle it in, child of the span of its sender and
+    /// following the current one, so that the handling appears in the trace of the sender.
+    pub(crate) async fn recv_in_span(&mut self) -> Option<(S, oneshot::Sender<R>, Span)> {
+        let (request, reply_tx, sender_span) = self.inner.recv().await?;
+        let current = Span::current();
+        let handler = current.metadata().map(|metadata| metadata.name());
+        let span = info_span!(parent: &sender_span, "roundtrip", handler);
+        span.follows_from(&current);
+        Some((request, reply_tx, span))
+    }
+}
+
 pub(crate) fn roundtrip_channel<S, R>(
     buffer: usize,
-) -> (RoundtripSender<S, R>, mpsc::Receiver<RequestPayload<S, R>>) {
+) -> (RoundtripSender<S, R>, RoundtripReceiver<S, R>) {
     let (inner, rx) = mpsc::channel(buffer);
     let sender = RoundtripSender { inner };
-    (sender, rx)
+    (sender, RoundtripReceiver { inner: rx })
 }
 
 #[cfg(test)]
 mod tests {
+    use tracing::Instrument;
+    use tracing_subscriber::registry::{LookupSpan, Registry, SpanData};
+
     use super::*;
 
     mod roundtrip {
@@ -60,7 +88,7 @@ mod tests {
         async fn request_send_timeout() {
             let (tx, _rx) = roundtrip_channel::<(), ()>(1);
             let (reply_tx, _) = oneshot::channel::<()>();
-            tx.inner.send(((), reply_tx)).await.unwrap();
+            tx.inner.send(((), reply_tx, Span::none())).await.unwrap();
             let result = tx.roundtrip(()).await;
             assert!(result.is_err());
         }
@@ -97,5 +125,31 @@ mod tests {
             let response = tx.roundtrip(54).await.unwrap();
             assert_eq!(response, 63);
         }
+
+        #[tokio::test]
+        async fn span() {
+            let _guard = tracing::subscriber::set_default(Registry::default());
+            let (tx, mut rx) = roundtrip_channel::<(), ()>(1);
+            let handler = tokio::spawn(
+                async move {
+                    let ((), reply_tx, span) = rx.recv_in_span().await.unwrap();
+                    reply_tx.send(()).unwrap();
+                    span
+                }
+                .instrument(info_span!("handler")),
+            );
+            let sender_span = info_span!("sender");
+            tx.roundtrip(())
+                .instrument(sender_span.clone())
+                .await
+                .unwrap();
+            let span = handler.await.unwrap();
+            tracing::dispatcher::get_default(|dispatch| {
+                let registry = dispatch.downcast_ref::<Registry>().unwrap();
+                let data = registry.span_data(&span.id().unwrap()).unwrap();
+                assert_eq!(data.metadata().name(), "roundtrip");
+                assert_eq!(data.parent(), sender_span.id().as_ref());
+            });
+        }
     }
 }
@@ -1,29 +1,64 @@
-use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 use anyhow::Context;
 use axum::http::StatusCode;
-use clap::Args;
-use futures_util::{TryFutureExt, TryStreamExt};
-use mongodb::Client;
-use mongodb::bson::{Bson, Document, doc};
-use mongodb::options::{ClientOptions, CountOptions, FindOptions};
+use clap::{Args, ValueEnum};
+use futures_util::TryStreamExt;
+use futures_util::future::{BoxFuture, FutureExt};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Bson, DateTime, Document, doc};
+use mongodb::error::{ErrorKind, TRANSIENT_TRANSACTION_ERROR, WriteFailure};
+use mongodb::options::{ClientOptions, FindOptions, IndexOptions, ReturnDocument};
+use mongodb::{Client, ClientSession, Collection, IndexModel};
+use tokio::sync::OnceCell;
 use tokio::task::JoinHandle;
 use tracing::{Instrument, debug, error, info, info_span, instrument, warn};
 
+use crate::audit::{HistoryEntry, WriteContext, WriteOperation, revision_summary};
+use crate::authorization::{FieldFilter, ReadAccess, RuleOperation, Rules};
 use crate::channel::{RoundtripSender, roundtrip_channel};
+use crate::interpolation::{
+    InterpolationContext, InterpolationError, Segment, parse_template, render,
+};
+use crate::layers::{Layer, LayersResolution, merge_layers};
+use crate::links::{DocumentLocation, LinksError, LinksResolution, extends_target, link_target};
+use crate::patch::{AtomicUpdate, Patch, PatchError};
+use crate::schema::{DocumentViolations, SCHEMA_ID, Schema, SchemaViolation, is_reserved_id};
 
+const DUPLICATE_KEY_CODE: i32 = 11000;
+const NAMESPACE_EXISTS_CODE: i32 = 48;
 const APP_NAME: &str = concat!(env!("CARGO_PKG_NAME"), " (", env!("CARGO_PKG_VERSION"), ")");
 
 #[derive(Args)]
 pub(crate) struct Config {
     /// URI of MongoDB server
-    #[arg(env, long, default_value = "mongodb://mongodb")]
+    #[arg(env, long, default_value = "mongodb://mongodb", hide_env_values = true)]
     mongodb_uri: String,
 
     /// MongoDB database
     #[arg(env, long)]
     mongodb_database: String,
+
+    /// Behavior when patching a document with a `_links` field
+    #[arg(env, long, value_enum, default_value_t = PatchLinksMode::Follow)]
+    patch_links: PatchLinksMode,
+
+    /// Maximum number of `_links` to follow when resolving a document
+    #[arg(env, long, default_value_t = 8)]
+    links_max_depth: usize,
+
+    /// Collection holding the variables of `${id.path}` placeholders
+    #[arg(env, long, default_value = "variables")]
+    variables_collection: String,
+
+    /// Collection holding the per-profile overlays of documents
+    #[arg(env, long, default_value = "profiles")]
+    profiles_collection: String,
+
+    /// Collection recording the history of the writes
+    #[arg(env, long, default_value = "history")]
+    history_collection: String,
 }
 
 pub(crate) type HealthChannel = RoundtripSender<(), bool>;
@@ -32,34 +67,258 @@ pub(crate) type HealthChannel = RoundtripSender<(), bool>;
 pub(crate) enum GetCollectionResponse {
     Documents(Vec<Document>),
     NotFound(String),
+    Interpolation(InterpolationError),
+}
+
+#[derive(Debug)]
+pub(crate) struct GetCollectionRequest {
+    pub(crate) collection: String,
+    /// Whether to resolve the placeholders of string values.
+    pub(crate) interpolate: bool,
+    /// Profiles whose overlays are merged on top of each document, in order.
+    pub(crate) profiles: Vec<String>,
+    /// Collections the placeholders may refer to.
+    pub(crate) access: ReadAccess,
 }
 
-pub(crate) type GetCollectionChannel = RoundtripSender<String, GetCollectionResponse>;
+pub(crate) type GetCollectionChannel = RoundtripSender<GetCollectionRequest, GetCollectionResponse>;
 
 #[derive(Debug)]
 pub(crate) struct GetDocumentRequest {
     pub(crate) collection: String,
     pub(crate) id: String,
+    /// Whether to follow links and merge layers.
+    pub(crate) resolve: bool,
+    /// Whether to explain which layer each value comes from.
+    pub(crate) explain: bool,
+    /// Whether to resolve the placeholders of string values.
+    pub(crate) interpolate: bool,
+    /// Profiles whose overlays are merged on top of the document, in order.
+    pub(crate) profiles: Vec<String>,
+    /// Collections the links, layers and placeholders may refer to.
+    pub(crate) access: ReadAccess,
 }
 
 #[derive(Debug)]
 pub(crate) enum GetDocumentResponse {
-    Document(Document),
+    Document {
+        document: Document,
+        links_path: Vec<DocumentLocation>,
+    },
     NotFound(String),
+    Links(LinksError),
+    Interpolation(InterpolationError),
 }
 
 pub(crate) type GetDocumentChannel = RoundtripSender<GetDocumentRequest, GetDocumentResponse>;
 
+#[derive(Debug)]
 pub(crate) struct PatchConfigRequest {
     pub(crate) collection: String,
     pub(crate) id: String,
-    pub(crate) changes: HashMap<String, Bson>,
+    pub(crate) patch: Patch,
+    pub(crate) context: WriteContext,
+    /// Collections the links may refer to.
+    pub(crate) access: ReadAccess,
+}
+
+#[derive(Debug)]
+pub(crate) enum PatchConfigResponse {
+    /// The patch has been applied to the last document of the links path.
+    Patched(Vec<DocumentLocation>),
+    Status(StatusCode),
+    Links(LinksError),
+    /// The patched document does not comply with the schema of the collection.
+    Invalid(Vec<SchemaViolation>),
+    /// The patch changes fields which are not granted to the caller (none if it is empty).
+    Forbidden(Vec<String>),
+    /// The patch reads values at paths which the caller can not read.
+    Unreadable(Vec<String>),
+    /// The patched document is a reserved one, with given id.
+    Reserved(String),
+    HistoryFailed(HistoryFailure),
+}
+
+/// The history entry of a write could not be recorded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct HistoryFailure {
+    /// Whether the write has been applied nonetheless, which it is on a standalone server.
+    pub(crate) applied: bool,
+}
+
+pub(crate) type PatchConfigChannel = RoundtripSender<PatchConfigRequest, PatchConfigResponse>;
+
+#[derive(Debug)]
+pub(crate) struct DeleteDocumentRequest {
+    pub(crate) collection: String,
+    pub(crate) id: String,
+    pub(crate) context: WriteContext,
+}
+
+#[derive(Debug)]
+pub(crate) enum DeleteDocumentResponse {
+    Status(StatusCode),
+    /// The deletion is not granted to the caller.
+    Forbidden,
+    /// The deleted document is a reserved one, with given id.
+    Reserved(String),
+    HistoryFailed(HistoryFailure),
+}
+
+pub(crate) type DeleteDocumentChannel =
+    RoundtripSender<DeleteDocumentRequest, DeleteDocumentResponse>;
+
+#[derive(Debug)]
+pub(crate) enum GetSchemaResponse {
+    Schema(Document),
+    NotFound(String),
+}
+
+pub(crate) type GetSchemaChannel = RoundtripSender<String, GetSchemaResponse>;
+
+#[derive(Debug)]
+pub(crate) struct PutSchemaRequest {
+    pub(crate) collection: String,
+    pub(crate) schema: Document,
+    pub(crate) context: WriteContext,
+}
+
+#[derive(Debug)]
+pub(crate) enum PutSchemaResponse {
+    Status(StatusCode),
+    /// The schema update is not granted to the caller.
+    Forbidden,
+    /// The schema is not a valid JSON Schema.
+    Invalid(String),
+    HistoryFailed(HistoryFailure),
+}
+
+pub(crate) type PutSchemaChannel = RoundtripSender<PutSchemaRequest, PutSchemaResponse>;
+
+#[derive(Debug)]
+pub(crate) struct ValidateCollectionRequest {
+    pub(crate) collection: String,
+    /// Schema to validate against instead of the stored one.
+    pub(crate) schema: Option<Document>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ValidateCollectionResponse {
+    /// Documents violating the schema.
+    Violations(Vec<DocumentViolations>),
+    NotFound(String),
+    InvalidSchema(String),
 }
 
-pub(crate) type PatchConfigChannel = RoundtripSender<PatchConfigRequest, StatusCode>;
+pub(crate) type ValidateCollectionChannel =
+    RoundtripSender<ValidateCollectionRequest, ValidateCollectionResponse>;
+
+#[derive(Debug)]
+pub(crate) struct GetHistoryRequest {
+    pub(crate) collection: String,
+    pub(crate) id: String,
+    /// Number of most recent revisions to skip.
+    pub(crate) skip: u64,
+    pub(crate) limit: i64,
+}
+
+#[derive(Debug)]
+pub(crate) enum GetHistoryResponse {
+    /// Revisions of the document, most recent first.
+    Revisions {
+        revisions: Vec<Document>,
+        total: u64,
+    },
+    NotFound(String),
+}
+
+pub(crate) type GetHistoryChannel = RoundtripSender<GetHistoryRequest, GetHistoryResponse>;
+
+#[derive(Debug)]
+pub(crate) struct GetRevisionRequest {
+    pub(crate) collection: String,
+    pub(crate) id: String,
+    pub(crate) revision: i64,
+}
+
+#[derive(Debug)]
+pub(crate) enum GetRevisionResponse {
+    /// Document as of the revision.
+    Document(Document),
+    NotFound(String),
+    /// The revision is a deletion of the document.
+    Deleted(String),
+}
+
+pub(crate) type GetRevisionChannel = RoundtripSender<GetRevisionRequest, GetRevisionResponse>;
+
+#[derive(Debug)]
+pub(crate) struct FindApiKeyRequest {
+    pub(crate) collection: String,
+    /// Hash of the API key.
+    pub(crate) hash: String,
+}
+
+pub(crate) type FindApiKeyChannel = RoundtripSender<FindApiKeyRequest, Option<Document>>;
+
+/// Outcome of patching a document.
+enum PatchOutcome {
+    Patched { before: Document, after: Document },
+    Status(StatusCode),
+    Invalid(Vec<SchemaViolation>),
+}
+
+/// Behavior of a patch on a document linking to another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum PatchLinksMode {
+    /// Patch the linked document.
+    Follow,
+    /// Refuse the patch.
+    Refuse,
+    /// Patch the linking document itself.
+    Alias,
+}
 
 #[derive(Clone)]
-pub(crate) struct Database(mongodb::Database);
+pub(crate) struct Database {
+    database: mongodb::Database,
+    patch_links: PatchLinksMode,
+    links_max_depth: usize,
+    variables_collection: String,
+    profiles_collection: String,
+    history_collection: String,
+    /// Collection holding the last revision of each document.
+    revisions_collection: String,
+    /// Whether the deployment supports transactions, detected on the first write.
+    transactions: Arc<OnceCell<bool>>,
+}
+
+/// Session in which a write and its history entry are made, within a transaction when the
+/// deployment supports them.
+struct WriteSession {
+    session: ClientSession,
+    transaction: bool,
+}
+
+/// Matches an id given as string, which may be the hexadecimal form of an `ObjectId`.
+fn string_id_filter(id: &str) -> Bson {
+    match ObjectId::parse_str(id) {
+        Ok(object_id) => doc! { "$in": [id, object_id] }.into(),
+        Err(_) => id.into(),
+    }
+}
+
+/// Whether an error comes from a transaction conflicting with a concurrent one.
+fn is_write_conflict(err: &mongodb::error::Error) -> bool {
+    err.contains_label(TRANSIENT_TRANSACTION_ERROR)
+}
+
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        &*err.kind,
+        ErrorKind::Write(WriteFailure::WriteError(error)) if error.code == DUPLICATE_KEY_CODE
+    )
+}
 
 impl Database {
     #[instrument(skip_all)]
@@ -72,7 +331,25 @@ impl Database {
         let client = Client::with_options(options).context("error creating the client")?;
         let database = client.database(&config.mongodb_database);
         info!(status = "success");
-        Ok(Self(database))
+        Ok(Self {
+            database,
+            patch_links: config.patch_links,
+            links_max_depth: config.links_max_depth,
+            variables_collection: config.variables_collection.clone(),
+            profiles_collection: config.profiles_collection.clone(),
+            history_collection: config.history_collection.clone(),
+            revisions_collection: format!("{}.revisions", config.history_collection),
+            transactions: Arc::default(),
+        })
+    }
+
+    /// Collections used by the service itself, not to be accessed as configuration.
+    pub(crate) fn internal_collections(&self) -> Vec<String> {
+        vec![
+            self.profiles_collection.clone(),
+            self.history_collection.clone(),
+            self.revisions_collection.clone(),
+        ]
     }
 
     pub(crate) fn handle_health(&self) -> (HealthChannel, JoinHandle<()>) {
@@ -83,12 +360,20 @@ impl Database {
         let task = tokio::spawn(
             async move {
                 info!(status = "started");
-                while let Some((_, response_tx)) = rx.recv().await {
-                    debug!(msg = "request received");
-                    let outcome = cloned_self.0.run_command(command.clone()).await.is_ok();
-                    if response_tx.send(outcome).is_err() {
-                        error!(kind = "outcome channel sending");
+                while let Some((_, response_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received");
+                        let outcome = cloned_self
+                            .database
+                            .run_command(command.clone())
+                            .await
+                            .is_ok();
+                        if response_tx.send(outcome).is_err() {
+                            error!(kind = "outcome channel sending");
+                        }
                     }
+                    .instrument(span)
+                    .await;
                 }
                 info!(status = "terminating");
             }
@@ -99,51 +384,126 @@ impl Database {
     }
 
     pub(crate) fn handle_get_collection(&self) -> (GetCollectionChannel, JoinHandle<()>) {
-        let (tx, mut rx) = roundtrip_channel::<String, GetCollectionResponse>(1);
+        let (tx, mut rx) = roundtrip_channel::<GetCollectionRequest, GetCollectionResponse>(1);
         let cloned_self = self.clone();
 
         let task = tokio::spawn(
             async move {
                 info!(status = "started");
 
-                while let Some((request, reply_tx)) = rx.recv().await {
-                    debug!(msg = "request received", collection = request);
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", ?request);
 
-                    let reply = |response: GetCollectionResponse| {
-                        if reply_tx.send(response).is_err() {
-                            error!(kind = "reply channel sending");
+                        let reply = |response: GetCollectionResponse| {
+                            if reply_tx.send(response).is_err() {
+                                error!(kind = "reply channel sending");
+                            }
+                        };
+                        if cloned_self
+                            .database
+                            .list_collection_names()
+                            .filter(doc! { "name": &request.collection })
+                            .await
+                            .unwrap_or_default()
+                            .is_empty()
+                        {
+                            reply(GetCollectionResponse::NotFound(format!(
+                                "Collection `{}` does not exist",
+                                request.collection
+                            )));
+                            return;
                         }
-                    };
-                    if cloned_self
-                        .0
-                        .list_collection_names()
-                        .filter(doc! { "name": &request })
-                        .await
-                        .unwrap_or_default()
-                        .is_empty()
-                    {
-                        reply(GetCollectionResponse::NotFound(format!(
-                            "Collection `{request}` does not exist"
-                        )));
-                        continue;
-                    }
-                    let collection = cloned_self.0.collection::<Document>(&request);
-                    let find_options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
-                    let cursor = match collection.find(doc! {}).with_options(find_options).await {
-                        Ok(cursor) => cursor,
-                        Err(err) => {
-                            error!(kind = "finding documents", %err);
-                            continue;
+                        let collection = cloned_self
+                            .database
+                            .collection::<Document>(&request.collection);
+                        let find_options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+                        let cursor = match collection.find(doc! {}).with_options(find_options).await
+                        {
+                            Ok(cursor) => cursor,
+                            Err(err) => {
+                                error!(kind = "finding documents", %err);
+                                return;
+                            }
+                        };
+                        let mut documents = match cursor.try_collect::<Vec<_>>().await {
+                            Ok(docs) => docs,
+                            Err(err) => {
+                                error!(kind = "collecting documents", %err);
+                                return;
+                            }
+                        };
+                        let overlays = match cloned_self
+                            .find_overlays(&request.collection, None, &request.profiles)
+                            .await
+                        {
+                            Ok(overlays) => overlays,
+                            Err(err) => {
+                                error!(kind = "finding overlays", %err);
+                                return;
+                            }
+                        };
+                        // Values may come from overlays, whose readable fields also apply.
+                        let mut collections = vec![request.collection.as_str()];
+                        if !overlays.is_empty() {
+                            collections.push(&cloned_self.profiles_collection);
                         }
-                    };
-                    let documents = match cursor.try_collect::<Vec<_>>().await {
-                        Ok(docs) => docs,
-                        Err(err) => {
-                            error!(kind = "collecting documents", %err);
-                            continue;
+                        let mut filters = Vec::with_capacity(collections.len());
+                        for collection in collections {
+                            match cloned_self.field_filter(collection).await {
+                                Ok(filter) => filters.push(filter),
+                                Err(err) => {
+                                    error!(kind = "authorization retrieval", %err);
+                                    return;
+                                }
+                            }
                         }
-                    };
-                    reply(GetCollectionResponse::Documents(documents));
+                        for document in &mut documents {
+                            let id = document.get("_id").cloned().unwrap_or_default();
+                            let layers =
+                                cloned_self.overlay_layers(&overlays, &id, &request.profiles);
+                            if !layers.is_empty() {
+                                let base = Layer {
+                                    location: DocumentLocation::new(&request.collection, id),
+                                    document: std::mem::take(document),
+                                };
+                                let layers = [vec![base], layers].concat();
+                                *document = merge_layers(&layers).0;
+                            }
+                            for filter in &filters {
+                                filter.redact(document);
+                            }
+                        }
+                        if !request.interpolate {
+                            reply(GetCollectionResponse::Documents(documents));
+                            return;
+                        }
+                        let mut context = InterpolationContext::new(request.access);
+                        let mut interpolated = Vec::with_capacity(documents.len());
+                        let mut failure = None;
+                        for document in documents {
+                            match cloned_self.interpolate(document.into(), &mut context).await {
+                                Ok(Bson::Document(document)) => interpolated.push(document),
+                                Ok(_) => unreachable!("documents are interpolated into documents"),
+                                Err(err) => {
+                                    failure = Some(err);
+                                    break;
+                                }
+                            }
+                        }
+                        match failure {
+                            None => reply(GetCollectionResponse::Documents(interpolated)),
+                            Some(InterpolationError::Database(err)) => {
+                                error!(kind = "interpolating documents", %err);
+                            }
+                            Some(err) => {
+                                warn!(msg = "interpolation failed", ?err);
+                                reply(GetCollectionResponse::Interpolation(err));
+                            }
+                        }
+                    }
+                    .instrument(span)
+                    .await;
                 }
 
                 info!(status = "terminating");
@@ -161,40 +521,22 @@ impl Database {
         let task = tokio::spawn(
             async move {
                 info!(status = "started");
-                while let Some((request, response_tx)) = rx.recv().await {
-                    debug!(msg = "request received", ?request);
-                    let collection = cloned_self.0.collection::<Document>(&request.collection);
-                    let mut document_id = request.id;
-                    let filter = doc! { "_id": &document_id };
-                    let found = collection
-                        .find_one(filter)
-                        .into_future()
-                        .and_then(|first_found| async {
-                            if let Some(Bson::ObjectId(links_id)) =
-                                first_found.as_ref().and_then(|doc| doc.get("_links"))
-                            {
-                                document_id = links_id.to_string();
-                                let filter = doc! { "_id": links_id };
-                                collection.find_one(filter).await
-                            } else {
-                                Ok(first_found)
-                            }
-                        })
-                        .await;
-                    let response = match found {
-                        Ok(Some(doc)) => GetDocumentResponse::Document(doc),
-                        Ok(None) => GetDocumentResponse::NotFound(format!(
-                            "Document with id `{}` not found in `{}` collection",
-                            document_id, request.collection
-                        )),
-                        Err(err) => {
-                            error!(during = "document finding", %err);
-                            continue;
+                while let Some((request, response_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", ?request);
+                        let response = match cloned_self.get_document(request).await {
+                            Ok(response) => response,
+                            Err(err) => {
+                                error!(during = "document finding", %err);
+                                return;
+                            }
+                        };
+                        if response_tx.send(response).is_err() {
+                            error!(kind = "outcome channel sending");
                         }
-                    };
-                    if response_tx.send(response).is_err() {
-                        error!(kind = "outcome channel sending");
                     }
+                    .instrument(span)
+                    .await;
                 }
                 info!(status = "terminating");
             }
@@ -205,61 +547,1311 @@ impl Database {
     }
 
     pub(crate) fn handle_patch_config(&self) -> (PatchConfigChannel, JoinHandle<()>) {
-        let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, StatusCode>(10);
+        let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(10);
         let cloned_self = self.clone();
 
         let task = tokio::spawn(
             async move {
                 info!(status = "started");
 
-                while let Some((request, reply_tx)) = rx.recv().await {
-                    let send_reply = |reply: StatusCode| {
-                        if reply_tx.send(reply).is_err() {
-                            error!(kind = "reply channel sending");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        let send_reply = |reply: PatchConfigResponse| {
+                            if reply_tx.send(reply).is_err() {
+                                error!(kind = "reply channel sending");
+                            }
+                        };
+                        let requested = DocumentLocation::new(request.collection, request.id);
+                        let links_path = if cloned_self.patch_links == PatchLinksMode::Alias {
+                            vec![requested]
+                        } else {
+                            match cloned_self.resolve_links(requested, &request.access).await {
+                                Ok(
+                                    LinksResolution::Found { path, .. }
+                                    | LinksResolution::NotFound(path),
+                                ) => path,
+                                Ok(LinksResolution::Failed(err)) => {
+                                    warn!(msg = "links resolution failed", ?err);
+                                    send_reply(PatchConfigResponse::Links(err));
+                                    return;
+                                }
+                                Err(err) => {
+                                    error!(kind = "links resolution", %err);
+                                    return;
+                                }
+                            }
+                        };
+                        if links_path.len() > 1 && cloned_self.patch_links == PatchLinksMode::Refuse
+                        {
+                            warn!(msg = "refusing to patch a linking document", ?links_path);
+                            send_reply(PatchConfigResponse::Status(StatusCode::CONFLICT));
+                            return;
                         }
-                    };
-                    let collection = cloned_self.0.collection::<Document>(&request.collection);
-                    let requested_changes_keys = request.changes.keys().collect::<Vec<_>>();
-                    let auth_document_filter = doc! {
-                        "_id": "_authorization",
-                        "patchAllowedFields": {
-                            "$all": &requested_changes_keys,
-                        },
-                    };
-                    let auth_document_options = CountOptions::builder().limit(1).build();
-                    match collection
-                        .count_documents(auth_document_filter)
-                        .with_options(auth_document_options)
-                        .await
-                    {
-                        Ok(0) => {
+                        let target = links_path.last().expect("path is never empty");
+                        // Patching its authorization would let a caller grant itself anything.
+                        if target.id.as_str().is_some_and(is_reserved_id) {
+                            warn!(
+                                msg = "refusing to patch a reserved document",
+                                target.collection,
+                                id = %target.id
+                            );
+                            send_reply(PatchConfigResponse::Reserved(target.id_string()));
+                            return;
+                        }
+                        let collection = cloned_self
+                            .database
+                            .collection::<Document>(&target.collection);
+                        let touched_fields = request.patch.touched_fields();
+                        let authorization = match collection
+                            .find_one(doc! { "_id": "_authorization" })
+                            .await
+                        {
+                            Ok(authorization) => authorization.unwrap_or_default(),
+                            Err(err) => {
+                                error!(kind = "authorization retrieval", target.collection, %err);
+                                return;
+                            }
+                        };
+                        // Copies, moves and tests would reveal the values they read.
+                        let field_filter = FieldFilter::from_authorization(Some(&authorization));
+                        let unreadable_paths = request
+                            .patch
+                            .read_paths()
+                            .into_iter()
+                            .filter(|path| !field_filter.is_wholly_readable(path))
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>();
+                        if !unreadable_paths.is_empty() {
+                            warn!(
+                                msg = "unreadable paths",
+                                target.collection,
+                                ?unreadable_paths
+                            );
+                            send_reply(PatchConfigResponse::Unreadable(unreadable_paths));
+                            return;
+                        }
+                        let denied_fields = match Rules::from_authorization(&authorization) {
+                            Ok(Some(rules)) => rules.denied_fields(
+                                &request.context,
+                                &target.id_string(),
+                                touched_fields.fields(),
+                            ),
+                            Ok(None) => {
+                                touched_fields.denied_by(&authorization, &request.context.roles)
+                            }
+                            Err(err) => {
+                                error!(kind = "authorization rules", target.collection, %err);
+                                return;
+                            }
+                        };
+                        if touched_fields.is_empty() || !denied_fields.is_empty() {
                             warn!(
                                 msg = "missing authorization",
-                                request.collection,
-                                ?requested_changes_keys
+                                target.collection,
+                                ?denied_fields,
+                                roles = ?request.context.roles
                             );
-                            send_reply(StatusCode::UNAUTHORIZED);
-                            continue;
+                            let denied_fields = denied_fields.into_iter().collect();
+                            send_reply(PatchConfigResponse::Forbidden(denied_fields));
+                            return;
                         }
-                        Err(err) => {
-                            error!(kind = "document count request", request.collection, %err);
+                        let schema = match Self::find_schema(&collection).await {
+                            Ok(schema) => schema,
+                            Err(err) => {
+                                error!(kind = "schema retrieval", target.collection, %err);
+                                return;
+                            }
+                        };
+                        let mut write = match cloned_self.start_write().await {
+                            Ok(write) => write,
+                            Err(err) => {
+                                error!(kind = "write session starting", %err);
+                                return;
+                            }
+                        };
+                        let outcome = Self::patch_document(
+                            &collection,
+                            &target.id,
+                            &request.patch,
+                            schema,
+                            &mut write,
+                        )
+                        .await;
+                        let (before, after) = match outcome {
+                            Ok(PatchOutcome::Patched { before, after }) => (before, after),
+                            Ok(PatchOutcome::Status(status)) => {
+                                send_reply(PatchConfigResponse::Status(status));
+                                return;
+                            }
+                            Ok(PatchOutcome::Invalid(violations)) => {
+                                send_reply(PatchConfigResponse::Invalid(violations));
+                                return;
+                            }
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", target.collection, %err);
+                                send_reply(PatchConfigResponse::Status(StatusCode::CONFLICT));
+                                return;
+                            }
+                            Err(err) => {
+                                error!(kind = "document updating", target.collection, %err);
+                                return;
+                            }
+                        };
+                        let entry = HistoryEntry {
+                            location: target,
+                            operation: WriteOperation::Patch,
+                            before: Some(&before),
+                            after: Some(&after),
+                            context: &request.context,
+                        };
+                        let transaction = write.transaction;
+                        match cloned_self.commit_write(write, entry).await {
+                            Ok(()) => send_reply(PatchConfigResponse::Patched(links_path)),
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", target.collection, %err);
+                                send_reply(PatchConfigResponse::Status(StatusCode::CONFLICT));
+                            }
+                            Err(err) => {
+                                error!(kind = "history recording", target.collection, %err);
+                                let failure = HistoryFailure {
+                                    applied: !transaction,
+                                };
+                                send_reply(PatchConfigResponse::HistoryFailed(failure));
+                            }
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_patch_config_handler")),
+        );
+
+        (tx, task)
+    }
+
+    async fn get_document(
+        &self,
+        request: GetDocumentRequest,
+    ) -> mongodb::error::Result<GetDocumentResponse> {
+        let not_found = |location: &DocumentLocation| {
+            GetDocumentResponse::NotFound(format!(
+                "Document with id `{}` not found in `{}` collection",
+                location.id_string(),
+                location.collection
+            ))
+        };
+        let location = DocumentLocation::new(request.collection, request.id);
+        let requested = location.clone();
+
+        if !request.resolve {
+            let found = self
+                .database
+                .collection::<Document>(&location.collection)
+                .find_one(doc! { "_id": &location.id })
+                .await?;
+            let filter = self.field_filter(&location.collection).await?;
+            return Ok(match found {
+                Some(mut document) => {
+                    filter.redact(&mut document);
+                    GetDocumentResponse::Document {
+                        document,
+                        links_path: vec![location],
+                    }
+                }
+                None => not_found(&location),
+            });
+        }
+
+        let (document, links_path) = match self.resolve_links(location, &request.access).await? {
+            LinksResolution::Found { document, path } => (document, path),
+            LinksResolution::NotFound(path) => {
+                return Ok(not_found(path.last().expect("path is never empty")));
+            }
+            LinksResolution::Failed(err) => {
+                warn!(msg = "links resolution failed", ?err);
+                return Ok(GetDocumentResponse::Links(err));
+            }
+        };
+        let top_layer = Layer {
+            location: links_path.last().expect("path is never empty").clone(),
+            document,
+        };
+        let layers = match self.resolve_layers(top_layer, &request.access).await? {
+            LayersResolution::Found(mut layers) => {
+                let overlays = self
+                    .find_overlays(
+                        &requested.collection,
+                        Some(&requested.id),
+                        &request.profiles,
+                    )
+                    .await?;
+                layers.extend(self.overlay_layers(&overlays, &requested.id, &request.profiles));
+                layers
+            }
+            LayersResolution::NotFound(path) => {
+                return Ok(not_found(path.last().expect("path is never empty")));
+            }
+            LayersResolution::Failed(err) => {
+                warn!(msg = "layers resolution failed", ?err);
+                return Ok(GetDocumentResponse::Links(err));
+            }
+        };
+        let (mut merged, sources) = merge_layers(&layers);
+        if request.interpolate {
+            match self
+                .interpolate(
+                    merged.into(),
+                    &mut InterpolationContext::new(request.access),
+                )
+                .await
+            {
+                Ok(Bson::Document(document)) => merged = document,
+                Ok(_) => unreachable!("documents are interpolated into documents"),
+                Err(InterpolationError::Database(err)) => return Err(err),
+                Err(err) => {
+                    warn!(msg = "interpolation failed", ?err);
+                    return Ok(GetDocumentResponse::Interpolation(err));
+                }
+            }
+        }
+        // Values may come from other collections, whose readable fields also apply.
+        let mut collections = layers
+            .iter()
+            .map(|layer| layer.location.collection.as_str())
+            .chain([requested.collection.as_str()])
+            .collect::<Vec<_>>();
+        collections.sort_unstable();
+        collections.dedup();
+        let mut filters = Vec::with_capacity(collections.len());
+        for collection in collections {
+            filters.push(self.field_filter(collection).await?);
+        }
+        for filter in &filters {
+            filter.redact(&mut merged);
+        }
+        let document = if request.explain {
+            let layers = layers
+                .iter()
+                .map(|layer| layer.location.to_string())
+                .collect::<Vec<_>>();
+            let sources = sources
+                .into_iter()
+                .filter(|(pointer, _)| {
+                    filters
+                        .iter()
+                        .all(|filter| filter.is_readable_path(pointer))
+                })
+                .map(|(pointer, source)| (pointer, Bson::String(source)))
+                .collect::<Document>();
+            doc! {
+                "document": merged,
+                "layers": layers,
+                "sources": sources,
+            }
+        } else {
+            merged
+        };
+        Ok(GetDocumentResponse::Document {
+            document,
+            links_path,
+        })
+    }
+
+    /// Finds the overlays of the documents of a collection (or only of the document with the
+    /// given id) for the given profiles.
+    async fn find_overlays(
+        &self,
+        collection: &str,
+        id: Option<&Bson>,
+        profiles: &[String],
+    ) -> mongodb::error::Result<Vec<Document>> {
+        if profiles.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut filter = doc! {
+            "profile": { "$in": profiles },
+            "collection": collection,
+        };
+        if let Some(id) = id {
+            filter.insert("id", id);
+        }
+        self.database
+            .collection::<Document>(&self.profiles_collection)
+            .find(filter)
+            .await?
+            .try_collect()
+            .await
+    }
+
+    /// Selects the overlays of a document among the found ones, in the order of the profiles.
+    fn overlay_layers(&self, overlays: &[Document], id: &Bson, profiles: &[String]) -> Vec<Layer> {
+        profiles
+            .iter()
+            .filter_map(|profile| {
+                let overlay = overlays.iter().find(|overlay| {
+                    overlay.get_str("profile").ok() == Some(profile)
+                        && overlay.get("id") == Some(id)
+                })?;
+                let document = overlay.get_document("overrides").ok()?.clone();
+                let overlay_id = overlay.get("_id")?.clone();
+                Some(Layer {
+                    location: DocumentLocation::new(&self.profiles_collection, overlay_id),
+                    document,
+                })
+            })
+            .collect()
+    }
+
+    /// Resolves the placeholders of the string values found in a value.
+    fn interpolate<'a>(
+        &'a self,
+        value: Bson,
+        context: &'a mut InterpolationContext,
+    ) -> BoxFuture<'a, Result<Bson, InterpolationError>> {
+        async move {
+            match value {
+                Bson::String(template) => {
+                    let segments = parse_template(&template, &self.variables_collection)?;
+                    let mut values = Vec::new();
+                    for segment in &segments {
+                        let Segment::Placeholder(placeholder) = segment else {
                             continue;
+                        };
+                        let collection = &placeholder.location.collection;
+                        // Variables are shared by all the collections.
+                        let allowed = if *collection == self.variables_collection {
+                            context.access.is_readable(collection)
+                        } else {
+                            context.access.allows(collection)
+                        };
+                        if !allowed {
+                            return Err(InterpolationError::Forbidden(placeholder.to_string()));
                         }
-                        Ok(_) => {}
+                        let key = placeholder.location.to_string();
+                        let document = match context.documents.get(&key) {
+                            Some(document) => document.clone(),
+                            None => {
+                                let mut document =
+                                    self.find_referenced(&placeholder.location).await?;
+                                if let Some(document) = &mut document {
+                                    self.field_filter(collection).await?.redact(document);
+                                }
+                                context.documents.insert(key, document.clone());
+                                document
+                            }
+                        };
+                        let value = document
+                            .map(Bson::Document)
+                            .and_then(|document| placeholder.pointer.get(&document).cloned())
+                            .ok_or_else(|| {
+                                InterpolationError::Unresolved(placeholder.to_string())
+                            })?;
+                        context.enter(placeholder)?;
+                        let value = self.interpolate(value, context).await?;
+                        context.leave();
+                        values.push(value);
                     }
-                    let update_filter = doc! { "_id": request.id };
-                    let update_document = request.changes.into_iter().collect::<Document>();
-                    let update = doc! { "$set": update_document };
-                    if let Err(err) = collection.update_one(update_filter, update).await {
-                        error!(kind = "document updating", request.collection, %err);
-                    } else {
-                        send_reply(StatusCode::OK);
+                    render(&segments, values)
+                }
+                Bson::Document(document) => {
+                    let mut interpolated = Document::new();
+                    for (key, value) in document {
+                        interpolated.insert(key, self.interpolate(value, context).await?);
                     }
+                    Ok(interpolated.into())
+                }
+                Bson::Array(array) => {
+                    let mut interpolated = Vec::with_capacity(array.len());
+                    for value in array {
+                        interpolated.push(self.interpolate(value, context).await?);
+                    }
+                    Ok(interpolated.into())
+                }
+                other => Ok(other),
+            }
+        }
+        .boxed()
+    }
+
+    /// Finds a document referenced by a placeholder, whose id may be a hexadecimal `ObjectId`.
+    async fn find_referenced(
+        &self,
+        location: &DocumentLocation,
+    ) -> mongodb::error::Result<Option<Document>> {
+        let filter = doc! { "_id": string_id_filter(&location.id_string()) };
+        self.database
+            .collection::<Document>(&location.collection)
+            .find_one(filter)
+            .await
+    }
+
+    /// Follows the `_extends` chain of a document, each extended document being itself resolved
+    /// by following its links.
+    async fn resolve_layers(
+        &self,
+        top_layer: Layer,
+        access: &ReadAccess,
+    ) -> mongodb::error::Result<LayersResolution> {
+        let mut layers = vec![top_layer];
+        loop {
+            let current = layers.last().expect("layers are never empty");
+            let Some(next) = extends_target(&current.document, &current.location.collection) else {
+                layers.reverse();
+                return Ok(LayersResolution::Found(layers));
+            };
+            let mut path = layers
+                .iter()
+                .map(|layer| layer.location.clone())
+                .collect::<Vec<_>>();
+            let cycle = path.contains(&next);
+            let too_deep = path.len() > self.links_max_depth;
+            path.push(next.clone());
+            if cycle {
+                return Ok(LayersResolution::Failed(LinksError::Cycle(path)));
+            }
+            if too_deep {
+                return Ok(LayersResolution::Failed(LinksError::TooDeep(path)));
+            }
+            if !access.allows(&next.collection) {
+                return Ok(LayersResolution::Failed(LinksError::Forbidden(path)));
+            }
+            match self.resolve_links(next, access).await? {
+                LinksResolution::Found {
+                    document,
+                    path: links_path,
+                } => {
+                    let location = links_path.last().expect("path is never empty").clone();
+                    layers.push(Layer { location, document });
+                }
+                LinksResolution::NotFound(links_path) => {
+                    return Ok(LayersResolution::NotFound(links_path));
+                }
+                LinksResolution::Failed(err) => return Ok(LayersResolution::Failed(err)),
+            }
+        }
+    }
+
+    /// Follows the links of a document, only to the collections the access allows.
+    async fn resolve_links(
+        &self,
+        location: DocumentLocation,
+        access: &ReadAccess,
+    ) -> mongodb::error::Result<LinksResolution> {
+        let mut path = vec![location];
+        loop {
+            let current = path.last().expect("path is never empty");
+            let Some(document) = self
+                .database
+                .collection::<Document>(&current.collection)
+                .find_one(doc! { "_id": &current.id })
+                .await?
+            else {
+                return Ok(LinksResolution::NotFound(path));
+            };
+            let Some(next) = link_target(&document, &current.collection) else {
+                return Ok(LinksResolution::Found { document, path });
+            };
+            let cycle = path.contains(&next);
+            let too_deep = path.len() > self.links_max_depth;
+            path.push(next);
+            if cycle {
+                return Ok(LinksResolution::Failed(LinksError::Cycle(path)));
+            }
+            if too_deep {
+                return Ok(LinksResolution::Failed(LinksError::TooDeep(path)));
+            }
+            let last = path.last().expect("path is never empty");
+            if !access.allows(&last.collection) {
+                return Ok(LinksResolution::Failed(LinksError::Forbidden(path)));
+            }
+        }
+    }
+
+    /// Finds and compiles the schema of a collection, if any.
+    async fn find_schema(collection: &Collection<Document>) -> anyhow::Result<Option<Schema>> {
+        let Some(document) = collection.find_one(doc! { "_id": SCHEMA_ID }).await? else {
+            return Ok(None);
+        };
+        let schema = document
+            .get_document("schema")
+            .context("schema document without `schema` object")?;
+        Schema::compile(schema)
+            .map(Some)
+            .map_err(anyhow::Error::msg)
+    }
+
+    /// Finds the fields of a collection documents callers can read.
+    async fn field_filter(&self, collection: &str) -> mongodb::error::Result<FieldFilter> {
+        let authorization = self
+            .database
+            .collection::<Document>(collection)
+            .find_one(doc! { "_id": "_authorization" })
+            .await?;
+        Ok(FieldFilter::from_authorization(authorization.as_ref()))
+    }
+
+    /// Checks that the `_authorization` document of a collection allows a deletion or a schema
+    /// update, through its rules if it has any.
+    async fn write_allowed(
+        collection: &Collection<Document>,
+        context: &WriteContext,
+        id: &str,
+        operation: RuleOperation,
+    ) -> anyhow::Result<bool> {
+        let Some(authorization) = collection
+            .find_one(doc! { "_id": "_authorization" })
+            .await?
+        else {
+            return Ok(false);
+        };
+        if let Some(rules) = Rules::from_authorization(&authorization)? {
+            return Ok(rules.allows(context, id, operation));
+        }
+        let legacy_field = match operation {
+            RuleOperation::Delete => "deleteAllowed",
+            RuleOperation::Put => "schemaUpdateAllowed",
+            RuleOperation::Patch => unreachable!("patches are authorized by field"),
+        };
+        Ok(authorization.get_bool(legacy_field).unwrap_or_default())
+    }
+
+    async fn patch_document(
+        collection: &Collection<Document>,
+        id: &Bson,
+        patch: &Patch,
+        schema: Option<Schema>,
+        write: &mut WriteSession,
+    ) -> mongodb::error::Result<PatchOutcome> {
+        let session = &mut write.session;
+        let id_filter = doc! { "_id": id };
+        let Some(original) = collection
+            .find_one(id_filter.clone())
+            .session(&mut *session)
+            .await?
+        else {
+            return Ok(PatchOutcome::Status(StatusCode::NOT_FOUND));
+        };
+
+        if let Some(AtomicUpdate {
+            preconditions,
+            update,
+        }) = patch.atomic_update()
+        {
+            let mut update_filter = id_filter.clone();
+            update_filter.extend(preconditions);
+            let result = match collection
+                .update_one(update_filter, update)
+                .session(&mut *session)
+                .await
+            {
+                Ok(result) => result,
+                Err(err) if matches!(*err.kind, ErrorKind::Write(_)) => {
+                    warn!(msg = "patch conflicts with document", %err);
+                    return Ok(PatchOutcome::Status(StatusCode::CONFLICT));
+                }
+                Err(err) => return Err(err),
+            };
+            if result.matched_count == 0 {
+                warn!(msg = "patch preconditions failed", %id);
+                return Ok(PatchOutcome::Status(StatusCode::CONFLICT));
+            }
+            let patched = collection
+                .find_one(id_filter)
+                .session(&mut *session)
+                .await?
+                .unwrap_or_default();
+            // The update is undone if its result does not comply with the schema.
+            let violations = schema.map(|schema| schema.validate(&patched));
+            if let Some(violations) = violations.filter(|violations| !violations.is_empty()) {
+                warn!(msg = "patched document does not comply with schema", %id, ?violations);
+                if write.transaction {
+                    session.abort_transaction().await?;
+                } else {
+                    let revert_filter = doc! {
+                        "_id": id,
+                        "$expr": { "$eq": ["$$ROOT", { "$literal": &patched }] },
+                    };
+                    let result = collection
+                        .replace_one(revert_filter, &original)
+                        .session(&mut *session)
+                        .await?;
+                    if result.matched_count == 0 {
+                        error!(kind = "patch reverting", %id, msg = "document modified concurrently");
+                    }
+                }
+                return Ok(PatchOutcome::Invalid(violations));
+            }
+            return Ok(PatchOutcome::Patched {
+                before: original,
+                after: patched,
+            });
+        }
+
+        // The patch can not be expressed as MongoDB update operators, apply it on the
+        // document and replace it if it has not been modified in the meantime.
+        let mut patched = original.clone();
+        if let Err(err) = patch.apply(&mut patched) {
+            warn!(msg = "error applying patch", %id, %err);
+            return Ok(PatchOutcome::Status(match err {
+                PatchError::NotFound(_) | PatchError::TestFailed(_) => StatusCode::CONFLICT,
+                PatchError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            }));
+        }
+        if patched.get("_id") != original.get("_id") {
+            warn!(msg = "patch modifies document id", %id);
+            return Ok(PatchOutcome::Status(StatusCode::UNPROCESSABLE_ENTITY));
+        }
+        if let Some(schema) = schema {
+            let violations = schema.validate(&patched);
+            if !violations.is_empty() {
+                warn!(msg = "patched document does not comply with schema", %id, ?violations);
+                return Ok(PatchOutcome::Invalid(violations));
+            }
+        }
+        let replace_filter = doc! {
+            "_id": id,
+            "$expr": { "$eq": ["$$ROOT", { "$literal": &original }] },
+        };
+        let result = collection
+            .replace_one(replace_filter, &patched)
+            .session(&mut *session)
+            .await?;
+        if result.matched_count == 0 {
+            warn!(msg = "document modified concurrently", %id);
+            return Ok(PatchOutcome::Status(StatusCode::CONFLICT));
+        }
+        Ok(PatchOutcome::Patched {
+            before: original,
+            after: patched,
+        })
+    }
+
+    /// Starts the session of a write, whose history entry is then recorded by
+    /// [`Self::commit_write`].
+    async fn start_write(&self) -> mongodb::error::Result<WriteSession> {
+        let transaction = *self
+            .transactions
+            .get_or_try_init(|| self.prepare_history())
+            .await?;
+        let mut session = self.database.client().start_session().await?;
+        if transaction {
+            session.start_transaction().await?;
+        }
+        Ok(WriteSession {
+            session,
+            transaction,
+        })
+    }
+
+    /// Indexes the history collection, and detects whether the deployment supports transactions,
+    /// which standalone servers do not.
+    async fn prepare_history(&self) -> mongodb::error::Result<bool> {
+        let index = IndexModel::builder()
+            .keys(doc! { "collection": 1, "id": 1, "revision": -1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        self.database
+            .collection::<Document>(&self.history_collection)
+            .create_index(index)
+            .await?;
+        // Created beforehand, as transactions can not always create collections.
+        if let Err(err) = self
+            .database
+            .create_collection(&self.revisions_collection)
+            .await
+            && !matches!(&*err.kind, ErrorKind::Command(error) if error.code == NAMESPACE_EXISTS_CODE)
+        {
+            return Err(err);
+        }
+        let hello = self.database.run_command(doc! { "hello": 1 }).await?;
+        let transaction =
+            hello.contains_key("setName") || hello.get_str("msg").ok() == Some("isdbgrid");
+        info!(msg = "history prepared", transaction);
+        Ok(transaction)
+    }
+
+    /// Records the history entry of a write with the next revision of the document, and commits
+    /// the write.
+    async fn commit_write(
+        &self,
+        mut write: WriteSession,
+        entry: HistoryEntry<'_>,
+    ) -> mongodb::error::Result<()> {
+        let revision = self.next_revision(&mut write, entry.location).await?;
+        self.database
+            .collection::<Document>(&self.history_collection)
+            .insert_one(entry.to_document(revision, DateTime::now()))
+            .session(&mut write.session)
+            .await?;
+        if write.transaction {
+            write.session.commit_transaction().await?;
+        }
+        Ok(())
+    }
+
+    /// Allocates the next revision of a document, by incrementing its counter, initialized from
+    /// the history of the document if it has none yet.
+    async fn next_revision(
+        &self,
+        write: &mut WriteSession,
+        location: &DocumentLocation,
+    ) -> mongodb::error::Result<i64> {
+        let counters = self
+            .database
+            .collection::<Document>(&self.revisions_collection);
+        let counter_filter = doc! {
+            "_id": { "collection": &location.collection, "id": &location.id },
+        };
+        loop {
+            let counter = counters
+                .find_one_and_update(
+                    counter_filter.clone(),
+                    doc! { "$inc": { "revision": 1_i64 } },
+                )
+                .return_document(ReturnDocument::After)
+                .session(&mut write.session)
+                .await?;
+            if let Some(counter) = counter {
+                return Ok(counter.get_i64("revision").unwrap_or_default());
+            }
+            let last = self
+                .database
+                .collection::<Document>(&self.history_collection)
+                .find_one(doc! { "collection": &location.collection, "id": &location.id })
+                .sort(doc! { "revision": -1 })
+                .session(&mut write.session)
+                .await?;
+            let revision = last
+                .and_then(|last| last.get_i64("revision").ok())
+                .unwrap_or_default()
+                + 1;
+            let mut counter = counter_filter.clone();
+            counter.insert("revision", revision);
+            match counters
+                .insert_one(counter)
+                .session(&mut write.session)
+                .await
+            {
+                Ok(_) => return Ok(revision),
+                // Initialized concurrently, which aborts a transaction.
+                Err(err) if is_duplicate_key(&err) && !write.transaction => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub(crate) fn handle_delete_document(&self) -> (DeleteDocumentChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<DeleteDocumentRequest, DeleteDocumentResponse>(10);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", ?request);
+                        let send_reply = |reply: DeleteDocumentResponse| {
+                            if reply_tx.send(reply).is_err() {
+                                error!(kind = "reply channel sending");
+                            }
+                        };
+                        let collection = cloned_self
+                            .database
+                            .collection::<Document>(&request.collection);
+                        let allowed = Self::write_allowed(
+                            &collection,
+                            &request.context,
+                            &request.id,
+                            RuleOperation::Delete,
+                        )
+                        .await;
+                        match allowed {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                warn!(
+                                    msg = "missing authorization",
+                                    request.collection, request.id
+                                );
+                                send_reply(DeleteDocumentResponse::Forbidden);
+                                return;
+                            }
+                            Err(err) => {
+                                error!(kind = "authorization retrieval", request.collection, %err);
+                                return;
+                            }
+                        }
+                        if is_reserved_id(&request.id) {
+                            warn!(
+                                msg = "refusing to delete a reserved document",
+                                request.collection, request.id
+                            );
+                            send_reply(DeleteDocumentResponse::Reserved(request.id));
+                            return;
+                        }
+                        let mut write = match cloned_self.start_write().await {
+                            Ok(write) => write,
+                            Err(err) => {
+                                error!(kind = "write session starting", %err);
+                                return;
+                            }
+                        };
+                        let delete_filter = doc! { "_id": &request.id };
+                        let deleted = match collection
+                            .find_one_and_delete(delete_filter)
+                            .session(&mut write.session)
+                            .await
+                        {
+                            Ok(Some(deleted)) => deleted,
+                            Ok(None) => {
+                                send_reply(DeleteDocumentResponse::Status(StatusCode::NOT_FOUND));
+                                return;
+                            }
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", request.collection, %err);
+                                send_reply(DeleteDocumentResponse::Status(StatusCode::CONFLICT));
+                                return;
+                            }
+                            Err(err) => {
+                                error!(kind = "document deleting", request.collection, %err);
+                                return;
+                            }
+                        };
+                        let location =
+                            DocumentLocation::new(&request.collection, request.id.as_str());
+                        let entry = HistoryEntry {
+                            location: &location,
+                            operation: WriteOperation::Delete,
+                            before: Some(&deleted),
+                            after: None,
+                            context: &request.context,
+                        };
+                        let transaction = write.transaction;
+                        match cloned_self.commit_write(write, entry).await {
+                            Ok(()) => {
+                                send_reply(DeleteDocumentResponse::Status(StatusCode::NO_CONTENT))
+                            }
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", request.collection, %err);
+                                send_reply(DeleteDocumentResponse::Status(StatusCode::CONFLICT));
+                            }
+                            Err(err) => {
+                                error!(kind = "history recording", request.collection, %err);
+                                let failure = HistoryFailure {
+                                    applied: !transaction,
+                                };
+                                send_reply(DeleteDocumentResponse::HistoryFailed(failure));
+                            }
+                        }
+                    }
+                    .instrument(span)
+                    .await;
                 }
 
                 info!(status = "terminating");
             }
-            .instrument(info_span!("mongodb_patch_config_handler")),
+            .instrument(info_span!("mongodb_delete_document_handler")),
+        );
+
+        (tx, task)
+    }
+
+    pub(crate) fn handle_get_schema(&self) -> (GetSchemaChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<String, GetSchemaResponse>(5);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((collection, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", collection);
+                        let found = cloned_self
+                            .database
+                            .collection::<Document>(&collection)
+                            .find_one(doc! { "_id": SCHEMA_ID })
+                            .await;
+                        let response = match found {
+                            Ok(Some(document)) => match document.get_document("schema") {
+                                Ok(schema) => GetSchemaResponse::Schema(schema.clone()),
+                                Err(err) => {
+                                    error!(kind = "schema document", collection, %err);
+                                    return;
+                                }
+                            },
+                            Ok(None) => GetSchemaResponse::NotFound(format!(
+                                "Collection `{collection}` has no schema"
+                            )),
+                            Err(err) => {
+                                error!(kind = "schema finding", collection, %err);
+                                return;
+                            }
+                        };
+                        if reply_tx.send(response).is_err() {
+                            error!(kind = "reply channel sending");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_get_schema_handler")),
+        );
+
+        (tx, task)
+    }
+
+    pub(crate) fn handle_put_schema(&self) -> (PutSchemaChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<PutSchemaRequest, PutSchemaResponse>(5);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", request.collection);
+                        let send_reply = |reply: PutSchemaResponse| {
+                            if reply_tx.send(reply).is_err() {
+                                error!(kind = "reply channel sending");
+                            }
+                        };
+                        let collection = cloned_self
+                            .database
+                            .collection::<Document>(&request.collection);
+                        let allowed = Self::write_allowed(
+                            &collection,
+                            &request.context,
+                            SCHEMA_ID,
+                            RuleOperation::Put,
+                        )
+                        .await;
+                        match allowed {
+                            Ok(true) => {}
+                            Ok(false) => {
+                                warn!(msg = "missing authorization", request.collection);
+                                send_reply(PutSchemaResponse::Forbidden);
+                                return;
+                            }
+                            Err(err) => {
+                                error!(kind = "authorization retrieval", request.collection, %err);
+                                return;
+                            }
+                        }
+                        if let Err(err) = Schema::compile(&request.schema) {
+                            warn!(msg = "invalid schema", request.collection, err);
+                            send_reply(PutSchemaResponse::Invalid(err));
+                            return;
+                        }
+                        let mut write = match cloned_self.start_write().await {
+                            Ok(write) => write,
+                            Err(err) => {
+                                error!(kind = "write session starting", %err);
+                                return;
+                            }
+                        };
+                        let schema_document = doc! { "_id": SCHEMA_ID, "schema": request.schema };
+                        let replaced = match collection
+                            .find_one_and_replace(doc! { "_id": SCHEMA_ID }, &schema_document)
+                            .upsert(true)
+                            .session(&mut write.session)
+                            .await
+                        {
+                            Ok(replaced) => replaced,
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", request.collection, %err);
+                                send_reply(PutSchemaResponse::Status(StatusCode::CONFLICT));
+                                return;
+                            }
+                            Err(err) => {
+                                error!(kind = "schema replacing", request.collection, %err);
+                                return;
+                            }
+                        };
+                        let status = if replaced.is_some() {
+                            StatusCode::NO_CONTENT
+                        } else {
+                            StatusCode::CREATED
+                        };
+                        let location = DocumentLocation::new(&request.collection, SCHEMA_ID);
+                        let entry = HistoryEntry {
+                            location: &location,
+                            operation: WriteOperation::PutSchema,
+                            before: replaced.as_ref(),
+                            after: Some(&schema_document),
+                            context: &request.context,
+                        };
+                        let transaction = write.transaction;
+                        match cloned_self.commit_write(write, entry).await {
+                            Ok(()) => send_reply(PutSchemaResponse::Status(status)),
+                            Err(err) if is_write_conflict(&err) => {
+                                warn!(msg = "concurrent write", request.collection, %err);
+                                send_reply(PutSchemaResponse::Status(StatusCode::CONFLICT));
+                            }
+                            Err(err) => {
+                                error!(kind = "history recording", request.collection, %err);
+                                let failure = HistoryFailure {
+                                    applied: !transaction,
+                                };
+                                send_reply(PutSchemaResponse::HistoryFailed(failure));
+                            }
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_put_schema_handler")),
+        );
+
+        (tx, task)
+    }
+
+    pub(crate) fn handle_validate_collection(&self) -> (ValidateCollectionChannel, JoinHandle<()>) {
+        let (tx, mut rx) =
+            roundtrip_channel::<ValidateCollectionRequest, ValidateCollectionResponse>(1);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", request.collection);
+                        let response = match cloned_self.validate_collection(request).await {
+                            Ok(response) => response,
+                            Err(err) => {
+                                error!(during = "collection validation", %err);
+                                return;
+                            }
+                        };
+                        if reply_tx.send(response).is_err() {
+                            error!(kind = "reply channel sending");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_validate_collection_handler")),
+        );
+
+        (tx, task)
+    }
+
+    async fn validate_collection(
+        &self,
+        request: ValidateCollectionRequest,
+    ) -> anyhow::Result<ValidateCollectionResponse> {
+        let collection = self.database.collection::<Document>(&request.collection);
+        let schema = match request.schema {
+            Some(schema) => match Schema::compile(&schema) {
+                Ok(schema) => schema,
+                Err(err) => return Ok(ValidateCollectionResponse::InvalidSchema(err)),
+            },
+            None => match Self::find_schema(&collection).await? {
+                Some(schema) => schema,
+                None => {
+                    return Ok(ValidateCollectionResponse::NotFound(format!(
+                        "Collection `{}` has no schema",
+                        request.collection
+                    )));
+                }
+            },
+        };
+        // Violations would reveal the fields the caller can not read.
+        let filter = self.field_filter(&request.collection).await?;
+        let find_options = FindOptions::builder().sort(doc! { "_id": 1 }).build();
+        let mut cursor = collection.find(doc! {}).with_options(find_options).await?;
+        let mut invalid_documents = Vec::new();
+        while let Some(mut document) = cursor.try_next().await? {
+            filter.redact(&mut document);
+            let violations = schema.validate(&document);
+            if !violations.is_empty() {
+                invalid_documents.push(DocumentViolations {
+                    id: document.get("_id").cloned().unwrap_or(Bson::Null),
+                    violations,
+                });
+            }
+        }
+        Ok(ValidateCollectionResponse::Violations(invalid_documents))
+    }
+
+    pub(crate) fn handle_get_history(&self) -> (GetHistoryChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<GetHistoryRequest, GetHistoryResponse>(5);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", ?request);
+                        let response = match cloned_self.get_history(request).await {
+                            Ok(response) => response,
+                            Err(err) => {
+                                error!(during = "history finding", %err);
+                                return;
+                            }
+                        };
+                        if reply_tx.send(response).is_err() {
+                            error!(kind = "reply channel sending");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_get_history_handler")),
+        );
+
+        (tx, task)
+    }
+
+    async fn get_history(
+        &self,
+        request: GetHistoryRequest,
+    ) -> mongodb::error::Result<GetHistoryResponse> {
+        let history = self
+            .database
+            .collection::<Document>(&self.history_collection);
+        let filter = doc! {
+            "collection": &request.collection,
+            "id": string_id_filter(&request.id),
+        };
+        let total = history.count_documents(filter.clone()).await?;
+        if total == 0 {
+            return Ok(GetHistoryResponse::NotFound(format!(
+                "Document with id `{}` has no history in `{}` collection",
+                request.id, request.collection
+            )));
+        }
+        let field_filter = self.field_filter(&request.collection).await?;
+        let revisions = history
+            .find(filter)
+            .sort(doc! { "revision": -1 })
+            .skip(request.skip)
+            .limit(request.limit)
+            .await?
+            .map_ok(|entry| {
+                let mut summary = revision_summary(&entry);
+                if let Ok(changes) = summary.get_array_mut("changes") {
+                    changes.retain(|change| {
+                        change
+                            .as_document()
+                            .and_then(|change| change.get_str("path").ok())
+                            .is_some_and(|path| field_filter.is_readable_path(path))
+                    });
+                }
+                summary
+            })
+            .try_collect()
+            .await?;
+        Ok(GetHistoryResponse::Revisions { revisions, total })
+    }
+
+    pub(crate) fn handle_get_revision(&self) -> (GetRevisionChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<GetRevisionRequest, GetRevisionResponse>(5);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", ?request);
+                        let filter = doc! {
+                            "collection": &request.collection,
+                            "id": string_id_filter(&request.id),
+                            "revision": request.revision,
+                        };
+                        let found = cloned_self
+                            .database
+                            .collection::<Document>(&cloned_self.history_collection)
+                            .find_one(filter)
+                            .await;
+                        let filter = match cloned_self.field_filter(&request.collection).await {
+                            Ok(filter) => filter,
+                            Err(err) => {
+                                error!(kind = "authorization retrieval", request.collection, %err);
+                                return;
+                            }
+                        };
+                        let response = match found {
+                            Ok(Some(entry)) => match entry.get_document("document") {
+                                Ok(document) => {
+                                    let mut document = document.clone();
+                                    filter.redact(&mut document);
+                                    GetRevisionResponse::Document(document)
+                                }
+                                Err(_) => GetRevisionResponse::Deleted(format!(
+                                    "Document with id `{}` has been deleted at revision {}",
+                                    request.id, request.revision
+                                )),
+                            },
+                            Ok(None) => GetRevisionResponse::NotFound(format!(
+                                "Document with id `{}` has no revision {} in `{}` collection",
+                                request.id, request.revision, request.collection
+                            )),
+                            Err(err) => {
+                                error!(kind = "revision finding", request.collection, %err);
+                                return;
+                            }
+                        };
+                        if reply_tx.send(response).is_err() {
+                            error!(kind = "reply channel sending");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_get_revision_handler")),
+        );
+
+        (tx, task)
+    }
+
+    pub(crate) fn handle_find_api_key(&self) -> (FindApiKeyChannel, JoinHandle<()>) {
+        let (tx, mut rx) = roundtrip_channel::<FindApiKeyRequest, Option<Document>>(10);
+        let cloned_self = self.clone();
+
+        let task = tokio::spawn(
+            async move {
+                info!(status = "started");
+                while let Some((request, reply_tx, span)) = rx.recv_in_span().await {
+                    async {
+                        debug!(msg = "request received", request.collection);
+                        let found = cloned_self
+                            .database
+                            .collection::<Document>(&request.collection)
+                            .find_one(doc! { "hash": &request.hash })
+                            .await;
+                        let key = match found {
+                            Ok(key) => key,
+                            Err(err) => {
+                                error!(kind = "API key finding", request.collection, %err);
+                                return;
+                            }
+                        };
+                        if reply_tx.send(key).is_err() {
+                            error!(kind = "reply channel sending");
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                info!(status = "terminating");
+            }
+            .instrument(info_span!("mongodb_find_api_key_handler")),
         );
 
         (tx, task)
@@ -0,0 +1,181 @@
+use mongodb::bson::{Bson, Document, doc};
+
+use crate::layers::pointer_token;
+
+/// Difference of a leaf value between two documents.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Change {
+    /// JSON pointer to the value.
+    pub(crate) path: String,
+    /// Value in the first document, if any.
+    pub(crate) old: Option<Bson>,
+    /// Value in the second document, if any.
+    pub(crate) new: Option<Bson>,
+}
+
+impl From<Change> for Bson {
+    fn from(change: Change) -> Self {
+        let mut document = doc! { "path": change.path };
+        if let Some(old) = change.old {
+            document.insert("old", old);
+        }
+        if let Some(new) = change.new {
+            document.insert("new", new);
+        }
+        document.into()
+    }
+}
+
+/// Lists the differences between two documents, by JSON pointer order.
+///
+/// Objects are recursively compared, while any other value (including arrays) is compared as a
+/// whole.
+pub(crate) fn diff_documents(old: &Document, new: &Document) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_into(old, new, "", &mut changes);
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Groups the differences from a left document to a right one into added, removed and changed
+/// values.
+pub(crate) fn diff_report(changes: Vec<Change>) -> Document {
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changed = Vec::new();
+    for Change { path, old, new } in changes {
+        match (old, new) {
+            (None, Some(value)) => added.push(doc! { "path": path, "value": value }),
+            (Some(value), None) => removed.push(doc! { "path": path, "value": value }),
+            (Some(left), Some(right)) => {
+                changed.push(doc! { "path": path, "left": left, "right": right });
+            }
+            (None, None) => {}
+        }
+    }
+    doc! {
+        "added": added,
+        "removed": removed,
+        "changed": changed,
+    }
+}
+
+fn diff_into(old: &Document, new: &Document, prefix: &str, changes: &mut Vec<Change>) {
+    for (key, old_value) in old {
+        let path = pointer_token(prefix, key);
+        match (old_value, new.get(key)) {
+            (Bson::Document(old_nested), Some(Bson::Document(new_nested))) => {
+                diff_into(old_nested, new_nested, &path, changes);
+            }
+            (_, Some(new_value)) if new_value == old_value => {}
+            (_, new_value) => changes.push(Change {
+                path,
+                old: Some(old_value.clone()),
+                new: new_value.cloned(),
+            }),
+        }
+    }
+    for (key, new_value) in new {
+        if !old.contains_key(key) {
+            changes.push(Change {
+                path: pointer_token(prefix, key),
+                old: None,
+                new: Some(new_value.clone()),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical() {
+        let document = doc! { "a": 1, "b": { "c": [1, 2] } };
+        assert!(diff_documents(&document, &document).is_empty());
+    }
+
+    #[test]
+    fn changes() {
+        let old = doc! {
+            "same": 1,
+            "changed": 1,
+            "removed": "x",
+            "nested": { "a": 1, "b": 2 },
+            "list": [1, 2],
+            "replaced": { "c": 3 },
+        };
+        let new = doc! {
+            "same": 1,
+            "changed": 2,
+            "nested": { "a": 1, "b": 3, "c/d": 4 },
+            "list": [1],
+            "replaced": "scalar",
+            "added": true,
+        };
+        let expected = vec![
+            Change {
+                path: "/added".into(),
+                old: None,
+                new: Some(true.into()),
+            },
+            Change {
+                path: "/changed".into(),
+                old: Some(1.into()),
+                new: Some(2.into()),
+            },
+            Change {
+                path: "/list".into(),
+                old: Some(vec![1, 2].into()),
+                new: Some(vec![1].into()),
+            },
+            Change {
+                path: "/nested/b".into(),
+                old: Some(2.into()),
+                new: Some(3.into()),
+            },
+            Change {
+                path: "/nested/c~1d".into(),
+                old: None,
+                new: Some(4.into()),
+            },
+            Change {
+                path: "/removed".into(),
+                old: Some("x".into()),
+                new: None,
+            },
+            Change {
+                path: "/replaced".into(),
+                old: Some(doc! { "c": 3 }.into()),
+                new: Some("scalar".into()),
+            },
+        ];
+        assert_eq!(diff_documents(&old, &new), expected);
+    }
+
+    #[test]
+    fn into_bson() {
+        let change = Change {
+            path: "/a".into(),
+            old: None,
+            new: Some(1.into()),
+        };
+        assert_eq!(
+            Bson::from(change),
+            Bson::from(doc! { "path": "/a", "new": 1 })
+        );
+    }
+
+    #[test]
+    fn report() {
+        let left = doc! { "a": 1, "b": 2 };
+        let right = doc! { "b": 3, "c": 4 };
+        let expected = doc! {
+            "added": [{ "path": "/c", "value": 4 }],
+            "removed": [{ "path": "/a", "value": 1 }],
+            "changed": [{ "path": "/b", "left": 2, "right": 3 }],
+        };
+        assert_eq!(diff_report(diff_documents(&left, &right)), expected);
+    }
+}
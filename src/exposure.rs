@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use clap::Args;
+
+use crate::authorization::matches_pattern;
+
+#[derive(Args)]
+#[group(id = "exposure")]
+pub(crate) struct Config {
+    /// Collections exposed by the service, as names or patterns in which `*` matches any
+    /// characters, all of them if not set
+    #[arg(env, long, value_delimiter = ',')]
+    exposed_collections: Vec<String>,
+
+    /// Disable all write routes
+    #[arg(env, long)]
+    read_only: bool,
+}
+
+/// Collections and operations exposed by the service, whatever the caller.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Exposure {
+    /// Patterns of the exposed collections, all of them if absent.
+    exposed_collections: Option<Arc<[String]>>,
+    pub(crate) read_only: bool,
+}
+
+impl From<&Config> for Exposure {
+    fn from(config: &Config) -> Self {
+        Self::new(&config.exposed_collections, config.read_only)
+    }
+}
+
+impl Exposure {
+    pub(crate) fn new(exposed_collections: &[String], read_only: bool) -> Self {
+        Self {
+            exposed_collections: (!exposed_collections.is_empty())
+                .then(|| exposed_collections.into()),
+            read_only,
+        }
+    }
+
+    pub(crate) fn exposed_collections(&self) -> Option<&[String]> {
+        self.exposed_collections.as_deref()
+    }
+
+    pub(crate) fn is_exposed(&self, collection: &str) -> bool {
+        self.exposed_collections.as_ref().is_none_or(|patterns| {
+            patterns
+                .iter()
+                .any(|pattern| matches_pattern(pattern, collection))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposed() {
+        let exposure = Exposure::from(&Config {
+            exposed_collections: vec!["machines".into(), "line-*".into()],
+            read_only: true,
+        });
+        assert!(exposure.is_exposed("machines"));
+        assert!(exposure.is_exposed("line-1"));
+        assert!(!exposure.is_exposed("secrets"));
+        assert!(Exposure::default().is_exposed("secrets"));
+        assert_eq!(Exposure::default().exposed_collections(), None);
+    }
+}
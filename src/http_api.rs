@@ -1,19 +1,68 @@
-use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use axum::extract::{Path, State};
-use axum::response::IntoResponse;
-use axum::{Json, Router, routing};
-use mongodb::bson::Bson;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{
+    ConnectInfo, FromRequestParts, MatchedPath, Path, Query, RawPathParams, Request, State,
+};
+use axum::http::header::{ALLOW, AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, HeaderValue, Method};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, Router, routing};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Document, doc};
 use reqwest::StatusCode;
-use tracing::{error, instrument};
+use serde::{Deserialize, Serialize};
+use tracing::{Instrument, error, info, info_span, instrument, warn};
+use tracing_subscriber::filter::Targets;
 
+use crate::access_log::AccessLog;
+use crate::audit::{WriteContext, restore_patch};
+use crate::auth::{AuthError, Authentication, Credentials, Identity, Scope};
+use crate::authorization::{CollectionFilter, ReadAccess};
 use crate::db::{
-    GetCollectionChannel, GetCollectionResponse, GetDocumentChannel, GetDocumentRequest,
-    GetDocumentResponse, HealthChannel, PatchConfigChannel, PatchConfigRequest,
+    DeleteDocumentChannel, DeleteDocumentRequest, DeleteDocumentResponse, GetCollectionChannel,
+    GetCollectionRequest, GetCollectionResponse, GetDocumentChannel, GetDocumentRequest,
+    GetDocumentResponse, GetHistoryChannel, GetHistoryRequest, GetHistoryResponse,
+    GetRevisionChannel, GetRevisionRequest, GetRevisionResponse, GetSchemaChannel,
+    GetSchemaResponse, HealthChannel, HistoryFailure, PatchConfigChannel, PatchConfigRequest,
+    PatchConfigResponse, PutSchemaChannel, PutSchemaRequest, PutSchemaResponse,
+    ValidateCollectionChannel, ValidateCollectionRequest, ValidateCollectionResponse,
 };
+use crate::diff::{diff_documents, diff_report};
+use crate::exposure::Exposure;
+use crate::interpolation::InterpolationError;
+use crate::links::{DocumentLocation, LinksError, format_path};
+use crate::logging::{FilterStatus, LogFilter};
+use crate::patch::Patch;
+use crate::problem::Problem;
+use crate::reload::{ReloadCounts, Reloadable, Reloads};
+use crate::schema::is_reserved_id;
+use crate::tls::TlsClient;
+use crate::trace_context::TraceContext;
 
 type HandlerError = (StatusCode, &'static str);
 
+const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+const PATCHED_DOCUMENT_HEADER: &str = "x-patched-document";
+const LINKS_PATH_HEADER: &str = "x-links-path";
+const PROFILE_HEADER: &str = "x-config-profile";
+const CALLER_HEADER: &str = "x-caller";
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const API_KEY_HEADER: &str = "x-api-key";
+const TRACEPARENT_HEADER: &str = "traceparent";
+const TRACERESPONSE_HEADER: &str = "traceresponse";
+
+/// Maximum number of revisions returned at once by the history route.
+const MAX_HISTORY_LIMIT: i64 = 100;
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+/// Seconds before a log filter set by the admin route is reverted, unless given.
+const DEFAULT_LOG_FILTER_DURATION: u64 = 15 * 60;
+const MAX_LOG_FILTER_DURATION: u64 = 24 * 60 * 60;
+
 const INTERNAL_ERROR: HandlerError = (StatusCode::INTERNAL_SERVER_ERROR, "internal server error");
 
 impl IntoResponse for GetCollectionResponse {
@@ -23,6 +72,70 @@ impl IntoResponse for GetCollectionResponse {
             GetCollectionResponse::NotFound(message) => {
                 (StatusCode::NOT_FOUND, message).into_response()
             }
+            GetCollectionResponse::Interpolation(err) => err.into_response(),
+        }
+    }
+}
+
+/// Returns the header listing the links resolution path, if any link has been followed.
+fn links_path_header(path: &[DocumentLocation]) -> Option<[(&'static str, String); 1]> {
+    (path.len() > 1).then(|| [(LINKS_PATH_HEADER, format_path(path))])
+}
+
+impl IntoResponse for LinksError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, title, path) = match self {
+            LinksError::Cycle(path) => (StatusCode::LOOP_DETECTED, "Links cycle detected", path),
+            LinksError::TooDeep(path) => (StatusCode::CONFLICT, "Links too deep", path),
+            LinksError::Forbidden(path) => (StatusCode::FORBIDDEN, "Link not authorized", path),
+        };
+        let last = path.last().map(ToString::to_string).unwrap_or_default();
+        let detail = match status {
+            StatusCode::LOOP_DETECTED => format!("`{last}` has already been visited"),
+            StatusCode::FORBIDDEN => format!("`{last}` is in a collection which is not readable"),
+            _ => format!("following the link to `{last}` exceeds the maximum depth"),
+        };
+        let links_path = path.iter().map(ToString::to_string).collect::<Vec<_>>();
+        Problem::new(status, title, detail)
+            .with("linksPath", links_path)
+            .into_response()
+    }
+}
+
+impl IntoResponse for InterpolationError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            InterpolationError::Invalid(detail) => {
+                Problem::new(StatusCode::CONFLICT, "Invalid placeholder", detail).into_response()
+            }
+            InterpolationError::Unresolved(placeholder) => Problem::new(
+                StatusCode::CONFLICT,
+                "Unresolved placeholder",
+                format!("`{placeholder}` does not refer to an existing value"),
+            )
+            .with("placeholder", placeholder)
+            .into_response(),
+            InterpolationError::Forbidden(placeholder) => Problem::new(
+                StatusCode::FORBIDDEN,
+                "Placeholder not authorized",
+                format!("`{placeholder}` refers to a collection which is not readable"),
+            )
+            .with("placeholder", placeholder)
+            .into_response(),
+            InterpolationError::Cycle(path) => {
+                let last = path.last().cloned().unwrap_or_default();
+                Problem::new(
+                    StatusCode::LOOP_DETECTED,
+                    "Placeholders cycle detected",
+                    format!("`{last}` is already being resolved"),
+                )
+                .with("placeholdersPath", path)
+                .into_response()
+            }
+            InterpolationError::Database(err) => {
+                error!(kind = "interpolation", %err);
+                INTERNAL_ERROR.into_response()
+            }
         }
     }
 }
@@ -30,10 +143,227 @@ impl IntoResponse for GetCollectionResponse {
 impl IntoResponse for GetDocumentResponse {
     fn into_response(self) -> axum::response::Response {
         match self {
-            GetDocumentResponse::Document(doc) => Json(doc).into_response(),
+            GetDocumentResponse::Document {
+                document,
+                links_path,
+            } => (links_path_header(&links_path), Json(document)).into_response(),
             GetDocumentResponse::NotFound(message) => {
                 (StatusCode::NOT_FOUND, message).into_response()
             }
+            GetDocumentResponse::Links(err) => err.into_response(),
+            GetDocumentResponse::Interpolation(err) => err.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for HistoryFailure {
+    fn into_response(self) -> axum::response::Response {
+        let detail = if self.applied {
+            "the write has been applied, but its history entry could not be recorded"
+        } else {
+            "the write has been canceled, as its history entry could not be recorded"
+        };
+        Problem::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "History recording failed",
+            detail.into(),
+        )
+        .with("applied", self.applied)
+        .into_response()
+    }
+}
+
+impl IntoResponse for PatchConfigResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            PatchConfigResponse::Patched(links_path) => {
+                let patched_id = links_path
+                    .last()
+                    .map(DocumentLocation::id_string)
+                    .unwrap_or_default();
+                (
+                    StatusCode::OK,
+                    [(PATCHED_DOCUMENT_HEADER, patched_id)],
+                    links_path_header(&links_path),
+                    (),
+                )
+                    .into_response()
+            }
+            PatchConfigResponse::Status(status) => status.into_response(),
+            PatchConfigResponse::Links(err) => err.into_response(),
+            PatchConfigResponse::Invalid(violations) => Problem::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Schema validation failed",
+                format!(
+                    "patched document violates {} schema keyword(s)",
+                    violations.len()
+                ),
+            )
+            .with("violations", violations)
+            .into_response(),
+            PatchConfigResponse::Forbidden(denied_fields) => {
+                let detail = if denied_fields.is_empty() {
+                    "the patch changes no field".into()
+                } else {
+                    format!(
+                        "the patch changes field(s) not granted to the caller: {}",
+                        denied_fields.join(", ")
+                    )
+                };
+                Problem::new(StatusCode::FORBIDDEN, "Patch not authorized", detail)
+                    .with("deniedFields", denied_fields)
+                    .into_response()
+            }
+            PatchConfigResponse::Unreadable(unreadable_paths) => {
+                let detail = format!(
+                    "the patch reads value(s) not readable by the caller: {}",
+                    unreadable_paths.join(", ")
+                );
+                Problem::new(StatusCode::FORBIDDEN, "Patch not authorized", detail)
+                    .with("unreadablePaths", unreadable_paths)
+                    .into_response()
+            }
+            PatchConfigResponse::Reserved(id) => reserved_problem(&id, "patched").into_response(),
+            PatchConfigResponse::HistoryFailed(failure) => failure.into_response(),
+        }
+    }
+}
+
+/// Refusal to write the `_authorization` or `_schema` document of a collection by a generic route.
+fn reserved_problem(id: &str, written: &str) -> Problem {
+    Problem::new(
+        StatusCode::FORBIDDEN,
+        "Reserved document",
+        format!("the `{id}` document is reserved, and can not be {written}"),
+    )
+}
+
+impl IntoResponse for GetSchemaResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            GetSchemaResponse::Schema(schema) => Json(schema).into_response(),
+            GetSchemaResponse::NotFound(message) => {
+                (StatusCode::NOT_FOUND, message).into_response()
+            }
+        }
+    }
+}
+
+fn invalid_schema_problem(detail: String) -> Problem {
+    Problem::new(StatusCode::UNPROCESSABLE_ENTITY, "Invalid schema", detail)
+}
+
+impl IntoResponse for PutSchemaResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            PutSchemaResponse::Status(status) => status.into_response(),
+            PutSchemaResponse::Forbidden => Problem::new(
+                StatusCode::FORBIDDEN,
+                "Schema update not authorized",
+                "the schema update is not granted to the caller".into(),
+            )
+            .into_response(),
+            PutSchemaResponse::Invalid(detail) => invalid_schema_problem(detail).into_response(),
+            PutSchemaResponse::HistoryFailed(failure) => failure.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for DeleteDocumentResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            DeleteDocumentResponse::Status(status) => status.into_response(),
+            DeleteDocumentResponse::Forbidden => Problem::new(
+                StatusCode::FORBIDDEN,
+                "Delete not authorized",
+                "the deletion of the document is not granted to the caller".into(),
+            )
+            .into_response(),
+            DeleteDocumentResponse::Reserved(id) => {
+                reserved_problem(&id, "deleted").into_response()
+            }
+            DeleteDocumentResponse::HistoryFailed(failure) => failure.into_response(),
+        }
+    }
+}
+
+impl IntoResponse for ValidateCollectionResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ValidateCollectionResponse::Violations(documents) => Json(doc! {
+                "valid": documents.is_empty(),
+                "documents": documents,
+            })
+            .into_response(),
+            ValidateCollectionResponse::NotFound(message) => {
+                (StatusCode::NOT_FOUND, message).into_response()
+            }
+            ValidateCollectionResponse::InvalidSchema(detail) => {
+                invalid_schema_problem(detail).into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for GetHistoryResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            GetHistoryResponse::Revisions { revisions, total } => Json(doc! {
+                "total": total as i64,
+                "revisions": revisions,
+            })
+            .into_response(),
+            GetHistoryResponse::NotFound(message) => {
+                (StatusCode::NOT_FOUND, message).into_response()
+            }
+        }
+    }
+}
+
+impl IntoResponse for GetRevisionResponse {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            GetRevisionResponse::Document(document) => Json(document).into_response(),
+            GetRevisionResponse::NotFound(message) => {
+                (StatusCode::NOT_FOUND, message).into_response()
+            }
+            GetRevisionResponse::Deleted(message) => (StatusCode::GONE, message).into_response(),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            AuthError::Missing => Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "Authentication required",
+                concat!(
+                    "an API key must be given in the `X-Api-Key` header, ",
+                    "a bearer token in the `Authorization` one, or a client certificate"
+                )
+                .into(),
+            )
+            .into_response(),
+            AuthError::Invalid => Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "Invalid API key",
+                "the given API key is unknown".into(),
+            )
+            .into_response(),
+            AuthError::InvalidToken(detail) => {
+                Problem::new(StatusCode::UNAUTHORIZED, "Invalid token", detail).into_response()
+            }
+            AuthError::UnknownCertificate(subject) => Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "Unknown client certificate",
+                format!("no principal is mapped to the `{subject}` certificate subject"),
+            )
+            .into_response(),
+            AuthError::Forbidden(detail) => {
+                Problem::new(StatusCode::FORBIDDEN, "Access forbidden", detail).into_response()
+            }
+            AuthError::Internal => INTERNAL_ERROR.into_response(),
         }
     }
 }
@@ -44,41 +374,408 @@ pub(crate) struct AppState {
     pub(crate) get_collection_channel: GetCollectionChannel,
     pub(crate) get_document_channel: GetDocumentChannel,
     pub(crate) patch_config_channel: PatchConfigChannel,
+    pub(crate) delete_document_channel: DeleteDocumentChannel,
+    pub(crate) get_schema_channel: GetSchemaChannel,
+    pub(crate) put_schema_channel: PutSchemaChannel,
+    pub(crate) validate_collection_channel: ValidateCollectionChannel,
+    pub(crate) get_history_channel: GetHistoryChannel,
+    pub(crate) get_revision_channel: GetRevisionChannel,
+    pub(crate) authentication: Reloadable<Authentication>,
+    pub(crate) collection_filter: Reloadable<CollectionFilter>,
+    pub(crate) exposure: Exposure,
+    pub(crate) access_log: AccessLog,
+    pub(crate) reloads: Reloads,
+    pub(crate) log_filter: LogFilter,
+}
+
+/// Adds the authentication and the exposure checks to configuration routes.
+fn checked(routes: Router<AppState>, app_state: &AppState) -> Router<AppState> {
+    routes
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            authenticate,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            check_exposure,
+        ))
 }
 
 pub(crate) fn app(app_state: AppState) -> Router {
-    Router::new()
-        .route("/health", routing::get(health_handler))
+    let config_routes = Router::new()
         .route("/config/{collection}", routing::get(get_collection_handler))
+        .route(
+            "/config/{collection}/_schema",
+            routing::get(get_schema_handler),
+        )
+        .route(
+            "/config/{collection}/_validate",
+            routing::post(validate_collection_handler),
+        )
+        .route("/config/{collection}/_diff", routing::get(diff_handler))
         .route(
             "/config/{collection}/{id}",
-            routing::get(get_document_handler).patch(patch_config_handler),
+            routing::get(get_document_handler)
+                .patch(patch_config_handler)
+                .delete(delete_document_handler),
+        )
+        .route(
+            "/config/{collection}/{id}/history",
+            routing::get(get_history_handler),
+        )
+        .route(
+            "/config/{collection}/{id}/history/{revision}",
+            routing::get(get_revision_handler),
         )
+        .route(
+            "/config/{collection}/{id}/history/{revision}/restore",
+            routing::post(restore_revision_handler),
+        );
+    // A schema constrains the writes of all the callers, and is only set by administrators.
+    let schema_routes = Router::new().route(
+        "/config/{collection}/_schema",
+        routing::put(put_schema_handler),
+    );
+    let schema_routes = checked(schema_routes, &app_state).route_layer(Extension(Scope::Admin));
+    let admin_routes = Router::new()
+        .route(
+            "/admin/log-filter",
+            routing::get(get_log_filter_handler)
+                .put(put_log_filter_handler)
+                .delete(delete_log_filter_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            authenticate,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            require_authentication,
+        ))
+        // Scope required by the authentication, instead of the read or write one.
+        .route_layer(Extension(Scope::Admin));
+    Router::new()
+        .route("/health", routing::get(health_handler))
+        .merge(checked(config_routes, &app_state))
+        .merge(schema_routes)
+        .merge(admin_routes)
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            log_access,
+        ))
+        .layer(middleware::from_fn(trace_request))
         .with_state(app_state)
 }
 
-#[instrument(name = "health_api_handler", skip_all)]
-async fn health_handler(State(state): State<AppState>) -> Result<StatusCode, HandlerError> {
-    state
-        .health_channel
-        .roundtrip(())
+/// Name of the authenticated caller, as recorded in the access log.
+#[derive(Clone)]
+struct Principal(String);
+
+/// Records the requests in the access log, as `access_log` events.
+async fn log_access(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+    if !state.access_log.samples(route.as_deref()) {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    let (mut collection, mut id) = (None, None);
+    let (mut parts, body) = request.into_parts();
+    if let Ok(params) = RawPathParams::from_request_parts(&mut parts, &()).await {
+        for (name, value) in &params {
+            match name {
+                "collection" => collection = Some(value.to_owned()),
+                "id" => id = Some(value.to_owned()),
+                _ => {}
+            }
+        }
+    }
+    let extensions = &parts.extensions;
+    let client = extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr)
+        .or_else(|| {
+            extensions
+                .get::<ConnectInfo<TlsClient>>()
+                .map(|ConnectInfo(client)| client.addr)
+        });
+    let start = Instant::now();
+    let response = next.run(Request::from_parts(parts, body)).await;
+    let principal = response
+        .extensions()
+        .get::<Principal>()
+        .map(|Principal(name)| name.as_str());
+    info!(
+        target: "access_log",
+        %method,
+        route,
+        collection,
+        id,
+        status = response.status().as_u16(),
+        latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+        bytes = response.body().size_hint().exact(),
+        client = client.map(|client| client.to_string()),
+        principal,
+    );
+    response
+}
+
+/// Checks that a request ID given by a client can be used, being short and printable.
+fn is_valid_request_id(request_id: &str) -> bool {
+    (1..=MAX_REQUEST_ID_LENGTH).contains(&request_id.len())
+        && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Handles a request in a span of the trace given by its `traceparent` header (or of a new trace),
+/// returning the context of the span in the `traceresponse` header.
+///
+/// The span also holds the ID of the request, given by its `X-Request-Id` header (or generated),
+/// which is returned in the same response header.
+async fn trace_request(mut request: Request, next: Next) -> Response {
+    let (context, request_id) = {
+        let headers = request.headers();
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        (
+            header(TRACEPARENT_HEADER)
+                .and_then(TraceContext::parse)
+                .map_or_else(TraceContext::root, |parent| parent.child()),
+            header(REQUEST_ID_HEADER)
+                .filter(|request_id| is_valid_request_id(request_id))
+                .map_or_else(|| ObjectId::new().to_hex(), str::to_owned),
+        )
+    };
+    let span = info_span!(
+        "http_request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id,
+        trace_id = context.trace_id(),
+        span_id = context.span_id(),
+    );
+    let request_id = HeaderValue::from_str(&request_id).expect("request ID is printable");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, request_id.clone());
+    let mut response = next.run(request).instrument(span).await;
+    let headers = response.headers_mut();
+    headers.insert(REQUEST_ID_HEADER, request_id);
+    if let Ok(value) = context.to_string().parse() {
+        headers.insert(TRACERESPONSE_HEADER, value);
+    }
+    response
+}
+
+/// Checks that a collection can be accessed at all.
+fn authorize_collection(filter: &CollectionFilter, collection: &str) -> Result<(), AuthError> {
+    if !filter.is_readable(collection) {
+        return Err(AuthError::Forbidden(format!(
+            "`{collection}` collection is not readable"
+        )));
+    }
+    Ok(())
+}
+
+/// Checks whether a configuration route writes the collection, which it does unless on `GET` and
+/// validation requests.
+fn is_write(request: &Request) -> bool {
+    request.method() != Method::GET && !request.uri().path().ends_with("/_validate")
+}
+
+/// Response to requests to collections which are not exposed by the service.
+fn not_exposed(collection: &str) -> Response {
+    Problem::new(
+        StatusCode::NOT_FOUND,
+        "Collection not exposed",
+        format!("`{collection}` collection is not exposed by this service"),
+    )
+    .into_response()
+}
+
+/// Rejects the requests to collections which are not exposed, and the writes in read-only mode.
+async fn check_exposure(
+    State(state): State<AppState>,
+    params: RawPathParams,
+    request: Request,
+    next: Next,
+) -> Response {
+    let collection = params
+        .iter()
+        .find_map(|(name, value)| (name == "collection").then_some(value));
+    if let Some(collection) = collection
+        && !state.exposure.is_exposed(collection)
+    {
+        warn!(msg = "collection not exposed", collection);
+        return not_exposed(collection);
+    }
+    if state.exposure.read_only && is_write(&request) {
+        warn!(msg = "write in read-only mode", method = %request.method());
+        // Restorations are the only writes without a read route.
+        let allow = if request.uri().path().ends_with("/restore") {
+            ""
+        } else {
+            "GET"
+        };
+        return (
+            [(ALLOW, allow)],
+            Problem::new(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "Read-only service",
+                format!(
+                    "`{}` requests are not allowed, this service is read-only",
+                    request.method()
+                ),
+            ),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Authenticates the caller of a configuration route, which reads the collection on `GET` and
+/// validation requests, and writes it otherwise, unless the collection is hidden.
+async fn authenticate(
+    State(state): State<AppState>,
+    params: RawPathParams,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let collection = params
+        .iter()
+        .find_map(|(name, value)| (name == "collection").then_some(value));
+    if let Some(collection) = collection
+        && let Err(err) = authorize_collection(&state.collection_filter.get(), collection)
+    {
+        warn!(msg = "hidden collection", collection);
+        return err.into_response();
+    }
+    let scope = match request.extensions().get::<Scope>() {
+        Some(scope) => *scope,
+        None if is_write(&request) => Scope::Write,
+        None => Scope::Read,
+    };
+    let headers = request.headers();
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let certificate = request
+        .extensions()
+        .get::<ConnectInfo<TlsClient>>()
+        .and_then(|ConnectInfo(client)| client.certificate_subject.as_deref());
+    let credentials = header(AUTHORIZATION.as_str())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(Credentials::Bearer)
+        .or_else(|| header(API_KEY_HEADER).map(Credentials::ApiKey))
+        .or_else(|| certificate.map(Credentials::Certificate));
+    match state
+        .authentication
+        .get()
+        .authenticate(credentials, collection, scope)
         .await
-        .map_err(|err| {
-            error!(kind = "health channel roundtrip", %err);
-            INTERNAL_ERROR
-        })?
-        .then_some(StatusCode::NO_CONTENT)
+    {
+        Ok(identity) => {
+            let Some(identity) = identity else {
+                return next.run(request).await;
+            };
+            let principal = Principal(identity.name.clone());
+            request.extensions_mut().insert(identity);
+            let mut response = next.run(request).await;
+            // For the access log.
+            response.extensions_mut().insert(principal);
+            response
+        }
+        Err(err) => {
+            warn!(msg = "authentication failed", ?err);
+            err.into_response()
+        }
+    }
+}
+
+/// Refuses the admin routes when authentication is disabled, as any caller would be allowed.
+async fn require_authentication(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.authentication.get().is_disabled() {
+        warn!(msg = "admin route called with authentication disabled");
+        return Problem::new(
+            StatusCode::FORBIDDEN,
+            "Authentication disabled",
+            "admin routes are only available when authentication is configured".into(),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+struct HealthQuery {
+    #[serde(default)]
+    details: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HealthDetails<'a> {
+    healthy: bool,
+    read_only: bool,
+    /// Patterns of the exposed collections, all of them if null.
+    exposed_collections: Option<&'a [String]>,
+    reloads: ReloadCounts,
+}
+
+#[instrument(name = "health_api_handler", skip_all)]
+async fn health_handler(
+    State(state): State<AppState>,
+    Query(query): Query<HealthQuery>,
+) -> Result<Response, HandlerError> {
+    let healthy = state.health_channel.roundtrip(()).await.map_err(|err| {
+        error!(kind = "health channel roundtrip", %err);
+        INTERNAL_ERROR
+    })?;
+    if query.details {
+        let status = if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        let details = HealthDetails {
+            healthy,
+            read_only: state.exposure.read_only,
+            exposed_collections: state.exposure.exposed_collections(),
+            reloads: state.reloads.counts(),
+        };
+        return Ok((status, Json(details)).into_response());
+    }
+    healthy
+        .then_some(StatusCode::NO_CONTENT.into_response())
         .ok_or(INTERNAL_ERROR)
 }
 
+#[derive(Deserialize)]
+struct GetCollectionQuery {
+    #[serde(default = "default_true")]
+    interpolate: bool,
+    profile: Option<String>,
+}
+
 #[instrument(name = "get_collection_api_handler", skip_all)]
 async fn get_collection_handler(
     State(state): State<AppState>,
     Path(collection): Path<String>,
+    Query(query): Query<GetCollectionQuery>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
 ) -> Result<GetCollectionResponse, HandlerError> {
+    let request = GetCollectionRequest {
+        collection,
+        interpolate: query.interpolate,
+        profiles: requested_profiles(query.profile.as_deref(), &headers),
+        access: read_access(&state, identity.as_ref()),
+    };
     state
         .get_collection_channel
-        .roundtrip(collection)
+        .roundtrip(request)
         .await
         .map_err(|err| {
             error!(kind = "collection retrieve channel roundtrip", %err);
@@ -86,12 +783,91 @@ async fn get_collection_handler(
         })
 }
 
+#[derive(Deserialize)]
+struct GetDocumentQuery {
+    #[serde(default = "default_true")]
+    resolve: bool,
+    #[serde(default)]
+    explain: bool,
+    #[serde(default = "default_true")]
+    interpolate: bool,
+    profile: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Returns the comma-separated profiles of the query parameter, or else of the header.
+fn requested_profiles(query: Option<&str>, headers: &HeaderMap) -> Vec<String> {
+    query
+        .or_else(|| {
+            headers
+                .get(PROFILE_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|profile| !profile.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Returns the collections the links, layers and placeholders of a request may refer to,
+/// exposed ones only.
+fn read_access(state: &AppState, identity: Option<&Extension<Identity>>) -> ReadAccess {
+    let collections = identity
+        .and_then(|Extension(identity)| identity.collections())
+        .map(<[String]>::to_vec);
+    ReadAccess::new(
+        state.collection_filter.get(),
+        state.exposure.clone(),
+        collections,
+    )
+}
+
+/// Returns the caller and the id of a write request, the latter being generated if not given.
+///
+/// The caller is the authenticated one, if authentication is enabled. Otherwise, the `X-Caller`
+/// header is only recorded as claimed.
+fn write_context(headers: &HeaderMap, identity: Option<Extension<Identity>>) -> WriteContext {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(str::to_owned)
+    };
+    let (caller, claimed_caller, roles) = match identity {
+        Some(Extension(identity)) => (Some(identity.name), None, identity.roles),
+        None => (None, header(CALLER_HEADER), Vec::new()),
+    };
+    WriteContext {
+        caller,
+        claimed_caller,
+        roles,
+        request_id: header(REQUEST_ID_HEADER).unwrap_or_else(|| ObjectId::new().to_hex()),
+    }
+}
+
 #[instrument(name = "get_document_api_handler", skip_all)]
 async fn get_document_handler(
     State(state): State<AppState>,
     Path((collection, id)): Path<(String, String)>,
+    Query(query): Query<GetDocumentQuery>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
 ) -> Result<GetDocumentResponse, HandlerError> {
-    let request = GetDocumentRequest { collection, id };
+    let request = GetDocumentRequest {
+        collection,
+        id,
+        resolve: query.resolve,
+        explain: query.explain,
+        interpolate: query.interpolate,
+        profiles: requested_profiles(query.profile.as_deref(), &headers),
+        access: read_access(&state, identity.as_ref()),
+    };
     state
         .get_document_channel
         .roundtrip(request)
@@ -102,16 +878,51 @@ async fn get_document_handler(
         })
 }
 
+fn parse_patch(headers: &HeaderMap, body: &Bytes) -> Result<Patch, (StatusCode, String)> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .unwrap_or_default();
+    match content_type.as_str() {
+        "application/json" => {
+            let Json(object) = Json::from_bytes(body)
+                .map_err(|rejection| (rejection.status(), rejection.body_text()))?;
+            return Patch::from_object(object)
+                .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err));
+        }
+        MERGE_PATCH_CONTENT_TYPE => {
+            Json::from_bytes(body).map(|Json(changes)| Patch::Merge(changes))
+        }
+        JSON_PATCH_CONTENT_TYPE => {
+            Json::from_bytes(body).map(|Json(operations)| Patch::Json(operations))
+        }
+        _ => {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("unsupported patch content type `{content_type}`"),
+            ));
+        }
+    }
+    .map_err(|rejection| (rejection.status(), rejection.body_text()))
+}
+
 #[instrument(name = "patch_config_api_handler", skip_all)]
 async fn patch_config_handler(
     State(state): State<AppState>,
     Path((collection, id)): Path<(String, String)>,
-    Json(changes): Json<HashMap<String, Bson>>,
-) -> Result<StatusCode, HandlerError> {
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<PatchConfigResponse, Response> {
+    let patch = parse_patch(&headers, &body).map_err(IntoResponse::into_response)?;
     let request = PatchConfigRequest {
         collection,
         id,
-        changes,
+        patch,
+        access: read_access(&state, identity.as_ref()),
+        context: write_context(&headers, identity),
     };
     state
         .patch_config_channel
@@ -119,39 +930,443 @@ async fn patch_config_handler(
         .await
         .map_err(|err| {
             error!(kind = "configuration patch channel roundtrip", %err);
+            INTERNAL_ERROR.into_response()
+        })
+}
+
+#[instrument(name = "delete_document_api_handler", skip_all)]
+async fn delete_document_handler(
+    State(state): State<AppState>,
+    Path((collection, id)): Path<(String, String)>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+) -> Result<DeleteDocumentResponse, HandlerError> {
+    let request = DeleteDocumentRequest {
+        collection,
+        id,
+        context: write_context(&headers, identity),
+    };
+    state
+        .delete_document_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "document delete channel roundtrip", %err);
             INTERNAL_ERROR
         })
 }
 
-#[cfg(test)]
-mod tests {
-    use axum::body::{Body, to_bytes};
-    use axum::http::Request;
-    use mongodb::bson::doc;
-    use tower::ServiceExt;
+#[instrument(name = "get_schema_api_handler", skip_all)]
+async fn get_schema_handler(
+    State(state): State<AppState>,
+    Path(collection): Path<String>,
+) -> Result<GetSchemaResponse, HandlerError> {
+    state
+        .get_schema_channel
+        .roundtrip(collection)
+        .await
+        .map_err(|err| {
+            error!(kind = "schema retrieve channel roundtrip", %err);
+            INTERNAL_ERROR
+        })
+}
 
-    use crate::channel::roundtrip_channel;
+#[instrument(name = "put_schema_api_handler", skip_all)]
+async fn put_schema_handler(
+    State(state): State<AppState>,
+    Path(collection): Path<String>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+    Json(schema): Json<Document>,
+) -> Result<PutSchemaResponse, HandlerError> {
+    let request = PutSchemaRequest {
+        collection,
+        schema,
+        context: write_context(&headers, identity),
+    };
+    state
+        .put_schema_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "schema update channel roundtrip", %err);
+            INTERNAL_ERROR
+        })
+}
 
-    use super::*;
+#[instrument(name = "validate_collection_api_handler", skip_all)]
+async fn validate_collection_handler(
+    State(state): State<AppState>,
+    Path(collection): Path<String>,
+    body: Bytes,
+) -> Result<ValidateCollectionResponse, Response> {
+    // Without body, documents are validated against the stored schema.
+    let schema = if body.is_empty() {
+        None
+    } else {
+        let Json(schema) = Json::from_bytes(&body).map_err(IntoResponse::into_response)?;
+        Some(schema)
+    };
+    let request = ValidateCollectionRequest { collection, schema };
+    state
+        .validate_collection_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "collection validation channel roundtrip", %err);
+            INTERNAL_ERROR.into_response()
+        })
+}
 
-    mod health_handler {
-        use super::*;
+#[derive(Deserialize)]
+struct GetHistoryQuery {
+    #[serde(default)]
+    skip: u64,
+    #[serde(default = "default_history_limit")]
+    limit: i64,
+}
 
-        fn testing_fixture(health_channel: HealthChannel) -> (Router, Request<Body>) {
-            let (get_collection_channel, _) = roundtrip_channel(1);
-            let (get_document_channel, _) = roundtrip_channel(1);
-            let (patch_config_channel, _) = roundtrip_channel(1);
-            let app = app(AppState {
-                health_channel,
-                get_collection_channel,
-                get_document_channel,
-                patch_config_channel,
-            });
-            let req = Request::builder()
-                .uri("/health")
-                .body(Body::empty())
-                .unwrap();
-            (app, req)
+fn default_history_limit() -> i64 {
+    20
+}
+
+#[instrument(name = "get_history_api_handler", skip_all)]
+async fn get_history_handler(
+    State(state): State<AppState>,
+    Path((collection, id)): Path<(String, String)>,
+    Query(query): Query<GetHistoryQuery>,
+) -> Result<GetHistoryResponse, HandlerError> {
+    if !(1..=MAX_HISTORY_LIMIT).contains(&query.limit) {
+        return Err((StatusCode::BAD_REQUEST, "limit must be between 1 and 100"));
+    }
+    let request = GetHistoryRequest {
+        collection,
+        id,
+        skip: query.skip,
+        limit: query.limit,
+    };
+    state
+        .get_history_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "history retrieve channel roundtrip", %err);
+            INTERNAL_ERROR
+        })
+}
+
+#[instrument(name = "get_revision_api_handler", skip_all)]
+async fn get_revision_handler(
+    State(state): State<AppState>,
+    Path((collection, id, revision)): Path<(String, String, i64)>,
+) -> Result<GetRevisionResponse, HandlerError> {
+    let request = GetRevisionRequest {
+        collection,
+        id,
+        revision,
+    };
+    state
+        .get_revision_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "revision retrieve channel roundtrip", %err);
+            INTERNAL_ERROR
+        })
+}
+
+/// Patches a document back to a previous revision, under the same authorization as any patch.
+#[instrument(name = "restore_revision_api_handler", skip_all)]
+async fn restore_revision_handler(
+    State(state): State<AppState>,
+    Path((collection, id, revision)): Path<(String, String, i64)>,
+    identity: Option<Extension<Identity>>,
+    headers: HeaderMap,
+) -> Result<Response, HandlerError> {
+    // A schema is only set by administrators, through its own route.
+    if is_reserved_id(&id) {
+        warn!(
+            msg = "refusing to restore a reserved document",
+            collection, id
+        );
+        return Ok(reserved_problem(&id, "restored").into_response());
+    }
+    let request = GetRevisionRequest {
+        collection: collection.clone(),
+        id: id.clone(),
+        revision,
+    };
+    let previous = match state
+        .get_revision_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "revision retrieve channel roundtrip", %err);
+            INTERNAL_ERROR
+        })? {
+        GetRevisionResponse::Document(document) => document,
+        response => return Ok(response.into_response()),
+    };
+    let request = GetDocumentRequest {
+        collection: collection.clone(),
+        id: id.clone(),
+        resolve: false,
+        explain: false,
+        interpolate: false,
+        profiles: Vec::new(),
+        access: read_access(&state, identity.as_ref()),
+    };
+    let current = match state
+        .get_document_channel
+        .roundtrip(request)
+        .await
+        .map_err(|err| {
+            error!(kind = "document retrieve channel roundtrip", %err);
+            INTERNAL_ERROR
+        })? {
+        GetDocumentResponse::Document { document, .. } => document,
+        // Restoring patches the document, so can not recreate a deleted one.
+        GetDocumentResponse::NotFound(_) => {
+            let detail =
+                format!("document `{id}` does not exist, deleted documents can not be restored");
+            return Ok(
+                Problem::new(StatusCode::NOT_FOUND, "Document not found", detail).into_response(),
+            );
+        }
+        response => return Ok(response.into_response()),
+    };
+    let patch = restore_patch(&current, &previous);
+    if patch.touched_fields().is_empty() {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    let request = PatchConfigRequest {
+        collection,
+        id,
+        patch,
+        access: read_access(&state, identity.as_ref()),
+        context: write_context(&headers, identity),
+    };
+    state
+        .patch_config_channel
+        .roundtrip(request)
+        .await
+        .map(IntoResponse::into_response)
+        .map_err(|err| {
+            error!(kind = "configuration patch channel roundtrip", %err);
+            INTERNAL_ERROR
+        })
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DiffQuery {
+    left: String,
+    right: String,
+    left_collection: Option<String>,
+    right_collection: Option<String>,
+    left_revision: Option<i64>,
+    right_revision: Option<i64>,
+}
+
+/// Returns a side of a diff, as of a revision or as currently stored, resolved if `resolve`.
+async fn diff_side(
+    state: &AppState,
+    collection: String,
+    id: String,
+    revision: Option<i64>,
+    resolve: bool,
+    access: ReadAccess,
+) -> Result<Document, Response> {
+    if let Some(revision) = revision {
+        let request = GetRevisionRequest {
+            collection,
+            id,
+            revision,
+        };
+        return match state.get_revision_channel.roundtrip(request).await {
+            Ok(GetRevisionResponse::Document(document)) => Ok(document),
+            Ok(response) => Err(response.into_response()),
+            Err(err) => {
+                error!(kind = "revision retrieve channel roundtrip", %err);
+                Err(INTERNAL_ERROR.into_response())
+            }
+        };
+    }
+    let request = GetDocumentRequest {
+        collection,
+        id,
+        resolve,
+        explain: false,
+        interpolate: resolve,
+        profiles: Vec::new(),
+        access,
+    };
+    match state.get_document_channel.roundtrip(request).await {
+        Ok(GetDocumentResponse::Document { document, .. }) => Ok(document),
+        Ok(response) => Err(response.into_response()),
+        Err(err) => {
+            error!(kind = "document retrieve channel roundtrip", %err);
+            Err(INTERNAL_ERROR.into_response())
+        }
+    }
+}
+
+#[instrument(name = "diff_api_handler", skip_all)]
+async fn diff_handler(
+    State(state): State<AppState>,
+    Path(collection): Path<String>,
+    Query(query): Query<DiffQuery>,
+    identity: Option<Extension<Identity>>,
+) -> Result<Json<Document>, Response> {
+    let left_collection = query.left_collection.unwrap_or_else(|| collection.clone());
+    let right_collection = query.right_collection.unwrap_or(collection);
+    // Only the collection of the path has been checked by the authentication.
+    for collection in [&left_collection, &right_collection] {
+        if !state.exposure.is_exposed(collection) {
+            return Err(not_exposed(collection));
+        }
+        authorize_collection(&state.collection_filter.get(), collection)
+            .map_err(IntoResponse::into_response)?;
+        if let Some(Extension(identity)) = &identity {
+            identity
+                .authorize_collection(collection)
+                .map_err(IntoResponse::into_response)?;
+        }
+    }
+    // Revisions are recorded as stored, so are compared with the stored documents.
+    let resolve = query.left_revision.is_none() && query.right_revision.is_none();
+    let mut left = diff_side(
+        &state,
+        left_collection,
+        query.left,
+        query.left_revision,
+        resolve,
+        read_access(&state, identity.as_ref()),
+    )
+    .await?;
+    let mut right = diff_side(
+        &state,
+        right_collection,
+        query.right,
+        query.right_revision,
+        resolve,
+        read_access(&state, identity.as_ref()),
+    )
+    .await?;
+    // Documents are compared by content, whatever their ids.
+    left.remove("_id");
+    right.remove("_id");
+    Ok(Json(diff_report(diff_documents(&left, &right))))
+}
+
+#[derive(Deserialize)]
+struct PutLogFilterBody {
+    filter: String,
+    /// Seconds before the filter is reverted.
+    #[serde(default = "default_log_filter_duration")]
+    duration: u64,
+}
+
+fn default_log_filter_duration() -> u64 {
+    DEFAULT_LOG_FILTER_DURATION
+}
+
+#[instrument(name = "get_log_filter_api_handler", skip_all)]
+async fn get_log_filter_handler(State(state): State<AppState>) -> Json<FilterStatus> {
+    Json(state.log_filter.status())
+}
+
+#[instrument(name = "put_log_filter_api_handler", skip_all)]
+async fn put_log_filter_handler(
+    State(state): State<AppState>,
+    Json(body): Json<PutLogFilterBody>,
+) -> Result<Json<FilterStatus>, Response> {
+    if !(1..=MAX_LOG_FILTER_DURATION).contains(&body.duration) {
+        let detail = format!("duration must be between 1 and {MAX_LOG_FILTER_DURATION} seconds");
+        return Err(
+            Problem::new(StatusCode::BAD_REQUEST, "Invalid duration", detail).into_response(),
+        );
+    }
+    let filter = body.filter.parse::<Targets>().map_err(|err| {
+        Problem::new(
+            StatusCode::BAD_REQUEST,
+            "Invalid log filter",
+            err.to_string(),
+        )
+        .into_response()
+    })?;
+    state
+        .log_filter
+        .set_temporary(filter, Duration::from_secs(body.duration))
+        .map_err(|err| {
+            error!(kind = "log filter change", %err);
+            INTERNAL_ERROR.into_response()
+        })?;
+    Ok(Json(state.log_filter.status()))
+}
+
+#[instrument(name = "delete_log_filter_api_handler", skip_all)]
+async fn delete_log_filter_handler(
+    State(state): State<AppState>,
+) -> Result<Json<FilterStatus>, HandlerError> {
+    state.log_filter.revert().map_err(|err| {
+        error!(kind = "log filter revert", %err);
+        INTERNAL_ERROR
+    })?;
+    Ok(Json(state.log_filter.status()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, to_bytes};
+    use axum::http::Request;
+    use mongodb::bson::{Bson, doc};
+    use tower::ServiceExt;
+
+    use crate::auth::{ApiKeys, hash_key};
+    use crate::channel::roundtrip_channel;
+    use crate::db::{FindApiKeyChannel, FindApiKeyRequest};
+    use crate::schema::{DocumentViolations, SchemaViolation};
+
+    use super::*;
+
+    /// State with closed channels, and neither authentication nor filtering.
+    impl Default for AppState {
+        fn default() -> Self {
+            Self {
+                health_channel: roundtrip_channel(1).0,
+                get_collection_channel: roundtrip_channel(1).0,
+                get_document_channel: roundtrip_channel(1).0,
+                patch_config_channel: roundtrip_channel(1).0,
+                delete_document_channel: roundtrip_channel(1).0,
+                get_schema_channel: roundtrip_channel(1).0,
+                put_schema_channel: roundtrip_channel(1).0,
+                validate_collection_channel: roundtrip_channel(1).0,
+                get_history_channel: roundtrip_channel(1).0,
+                get_revision_channel: roundtrip_channel(1).0,
+                authentication: Authentication::default().into(),
+                collection_filter: CollectionFilter::default().into(),
+                exposure: Exposure::default(),
+                access_log: AccessLog::default(),
+                reloads: Reloads::default(),
+                log_filter: LogFilter::default(),
+            }
+        }
+    }
+
+    mod health_handler {
+        use super::*;
+
+        fn testing_fixture(health_channel: HealthChannel) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                health_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
         }
 
         #[tokio::test]
@@ -185,6 +1400,82 @@ mod tests {
             let res = app.oneshot(req).await.unwrap();
             assert_eq!(res.status(), StatusCode::NO_CONTENT);
         }
+
+        #[tokio::test]
+        async fn details() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx.send(false).expect("error sending response");
+            });
+            let (app, _) = testing_fixture(tx);
+            let req = Request::builder()
+                .uri("/health?details=true")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"healthy":false,"readOnly":false,"exposedCollections":null,"#,
+                    r#""reloads":{"succeeded":0,"failed":0}}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn traced() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx.send(true).expect("error sending response");
+            });
+            let (app, _) = testing_fixture(tx);
+            let req = Request::builder()
+                .uri("/health")
+                .header(
+                    "traceparent",
+                    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+                )
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+            let context = res.headers()["traceresponse"].to_str().unwrap();
+            assert!(context.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+            assert!(!context.contains("00f067aa0ba902b7"));
+        }
+
+        #[tokio::test]
+        async fn request_id() {
+            for (given, expected_length) in [
+                (Some("somerequest"), 11),
+                (Some("some request"), 24),
+                (None, 24),
+            ] {
+                let (tx, mut rx) = roundtrip_channel(1);
+                tokio::spawn(async move {
+                    let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                    response_tx.send(true).expect("error sending response");
+                });
+                let (app, _) = testing_fixture(tx);
+                let mut builder = Request::builder().uri("/health");
+                if let Some(given) = given {
+                    builder = builder.header("X-Request-Id", given);
+                }
+                let res = app
+                    .oneshot(builder.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let request_id = res.headers()["X-Request-Id"].to_str().unwrap();
+                assert_eq!(request_id.len(), expected_length, "{given:?}");
+                if expected_length == 11 {
+                    assert_eq!(request_id, "somerequest");
+                }
+            }
+        }
     }
 
     mod get_collection_handler {
@@ -193,14 +1484,9 @@ mod tests {
         fn testing_fixture(
             get_collection_channel: GetCollectionChannel,
         ) -> (Router, Request<Body>) {
-            let (health_channel, _) = roundtrip_channel(1);
-            let (get_document_channel, _) = roundtrip_channel(1);
-            let (patch_config_channel, _) = roundtrip_channel(1);
             let app = app(AppState {
-                health_channel,
                 get_collection_channel,
-                get_document_channel,
-                patch_config_channel,
+                ..AppState::default()
             });
             let req = Request::builder()
                 .uri("/config/somecollection")
@@ -219,11 +1505,11 @@ mod tests {
 
         #[tokio::test]
         async fn not_found() {
-            let (tx, mut rx) = roundtrip_channel(1);
+            let (tx, mut rx) = roundtrip_channel::<GetCollectionRequest, GetCollectionResponse>(1);
             tokio::spawn(async move {
                 let (request, response_tx) = rx.recv().await.expect("channel has been closed");
                 response_tx
-                    .send(GetCollectionResponse::NotFound(request))
+                    .send(GetCollectionResponse::NotFound(request.collection))
                     .expect("error sending response");
             });
             let (app, req) = testing_fixture(tx);
@@ -235,13 +1521,13 @@ mod tests {
 
         #[tokio::test]
         async fn success() {
-            let (tx, mut rx) = roundtrip_channel(1);
+            let (tx, mut rx) = roundtrip_channel::<GetCollectionRequest, GetCollectionResponse>(1);
             tokio::spawn(async move {
                 let (request, response_tx) = rx.recv().await.expect("channel has been closed");
                 response_tx
                     .send(GetCollectionResponse::Documents(vec![
                         doc! { "a": 1, "b": "c" },
-                        doc! { "a": 2, "b": request },
+                        doc! { "a": 2, "b": request.collection },
                     ]))
                     .expect("error sending response");
             });
@@ -252,20 +1538,56 @@ mod tests {
             let body = to_bytes(res.into_body(), 1024).await.unwrap();
             assert_eq!(body, r#"[{"a":1,"b":"c"},{"a":2,"b":"somecollection"}]"#);
         }
+
+        #[tokio::test]
+        async fn query_parameters() {
+            let (tx, mut rx) = roundtrip_channel::<GetCollectionRequest, GetCollectionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert!(!request.interpolate);
+                assert_eq!(request.profiles, ["plant-a"]);
+                response_tx
+                    .send(GetCollectionResponse::Documents(Vec::new()))
+                    .expect("error sending response");
+            });
+            let (app, _) = testing_fixture(tx);
+            let req = Request::builder()
+                .uri("/config/somecollection?interpolate=false&profile=plant-a")
+                .header("X-Config-Profile", "plant-b")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn profile_header() {
+            let (tx, mut rx) = roundtrip_channel::<GetCollectionRequest, GetCollectionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.profiles, ["plant-a", "line-2"]);
+                response_tx
+                    .send(GetCollectionResponse::Documents(Vec::new()))
+                    .expect("error sending response");
+            });
+            let (app, _) = testing_fixture(tx);
+            let req = Request::builder()
+                .uri("/config/somecollection")
+                .header("X-Config-Profile", "plant-a,line-2")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
     }
 
     mod get_document_handler {
         use super::*;
 
         fn testing_fixture(get_document_channel: GetDocumentChannel) -> (Router, Request<Body>) {
-            let (health_channel, _) = roundtrip_channel(1);
-            let (get_collection_channel, _) = roundtrip_channel(1);
-            let (patch_config_channel, _) = roundtrip_channel(1);
             let app = app(AppState {
-                health_channel,
-                get_collection_channel,
                 get_document_channel,
-                patch_config_channel,
+                ..AppState::default()
             });
             let req = Request::builder()
                 .uri("/config/somecoll/someid")
@@ -297,10 +1619,41 @@ mod tests {
             let body = to_bytes(res.into_body(), 1024).await.unwrap();
             assert_eq!(
                 body,
-                r#"GetDocumentRequest { collection: "somecoll", id: "someid" }"#
+                concat!(
+                    r#"GetDocumentRequest { collection: "somecoll", id: "someid", "#,
+                    r#"resolve: true, explain: false, interpolate: true, profiles: [], "#,
+                    r#"access: ReadAccess { filter: CollectionFilter { readable: [], "#,
+                    r#"hidden: [], internal: [] }, exposure: Exposure { "#,
+                    r#"exposed_collections: None, read_only: false }, collections: None } }"#
+                )
             );
         }
 
+        #[tokio::test]
+        async fn exposed_access() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                // Links and placeholders can not leave the exposed collections.
+                assert!(request.access.allows("line-1"));
+                assert!(!request.access.allows("secrets"));
+                response_tx
+                    .send(GetDocumentResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let app = app(AppState {
+                get_document_channel: tx,
+                exposure: Exposure::new(&["line-*".into()], false),
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri("/config/line-1/someid")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+
         #[tokio::test]
         async fn document_response() {
             let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
@@ -310,61 +1663,1734 @@ mod tests {
                     "collection": request.collection.as_str(),
                     "id": request.id.as_str(),
                 };
+                let links_path = vec![DocumentLocation::new(request.collection, request.id)];
                 response_tx
-                    .send(GetDocumentResponse::Document(document))
+                    .send(GetDocumentResponse::Document {
+                        document,
+                        links_path,
+                    })
                     .expect("error sending response");
             });
             let (app, req) = testing_fixture(tx);
             let res = app.oneshot(req).await.unwrap();
             assert_eq!(res.status(), StatusCode::OK);
             assert_eq!(res.headers()["Content-Type"], "application/json");
+            assert!(res.headers().get("X-Links-Path").is_none());
             let body = to_bytes(res.into_body(), 1024).await.unwrap();
             assert_eq!(body, r#"{"collection":"somecoll","id":"someid"}"#);
         }
-    }
-
-    mod patch_config_handler {
-        use super::*;
 
-        fn testing_fixture(patch_config_channel: PatchConfigChannel) -> (Router, Request<Body>) {
-            let (health_channel, _) = roundtrip_channel(1);
-            let (get_collection_channel, _) = roundtrip_channel(1);
-            let (get_document_channel, _) = roundtrip_channel(1);
-            let app = app(AppState {
-                health_channel,
-                get_collection_channel,
-                get_document_channel,
-                patch_config_channel,
+        #[tokio::test]
+        async fn query_parameters() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert!(!request.resolve);
+                assert!(request.explain);
+                assert!(!request.interpolate);
+                assert_eq!(request.profiles, ["plant-a", "line-2"]);
+                response_tx
+                    .send(GetDocumentResponse::NotFound(String::new()))
+                    .expect("error sending response");
             });
+            let (app, _) = testing_fixture(tx);
             let req = Request::builder()
-                .method("PATCH")
-                .uri("/config/somecoll/someid")
-                .header("Content-Type", "application/json")
-                .body(Body::from(r#"{"somekey":42,"otherkey":["a","b"]}"#))
+                .uri("/config/somecoll/someid?resolve=false&explain=true&interpolate=false&profile=plant-a,line-2")
+                .header("X-Config-Profile", "plant-b")
+                .body(Body::empty())
                 .unwrap();
-            (app, req)
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
         }
 
         #[tokio::test]
-        async fn roundtrip_error() {
-            let (tx, _) = roundtrip_channel(1);
-            let (app, req) = testing_fixture(tx);
+        async fn profile_header() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.profiles, ["plant-a", "line-2"]);
+                response_tx
+                    .send(GetDocumentResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (app, _) = testing_fixture(tx);
+            let req = Request::builder()
+                .uri("/config/somecoll/someid")
+                .header("X-Config-Profile", " plant-a, ,line-2")
+                .body(Body::empty())
+                .unwrap();
             let res = app.oneshot(req).await.unwrap();
-            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
         }
 
         #[tokio::test]
-        async fn status_code_response() {
-            let (tx, mut rx) = roundtrip_channel(1);
+        async fn linked_document_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
             tokio::spawn(async move {
-                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let links_path = vec![
+                    DocumentLocation::new(request.collection, request.id),
+                    DocumentLocation::new("templates", "base"),
+                ];
                 response_tx
-                    .send(StatusCode::IM_A_TEAPOT)
+                    .send(GetDocumentResponse::Document {
+                        document: doc! { "a": 1 },
+                        links_path,
+                    })
                     .expect("error sending response");
             });
             let (app, req) = testing_fixture(tx);
             let res = app.oneshot(req).await.unwrap();
-            assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                res.headers()["X-Links-Path"],
+                "somecoll/someid, templates/base"
+            );
+        }
+
+        #[tokio::test]
+        async fn links_cycle_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let links_path = vec![
+                    DocumentLocation::new(&request.collection, request.id.as_str()),
+                    DocumentLocation::new(&request.collection, "other"),
+                    DocumentLocation::new(request.collection, request.id),
+                ];
+                response_tx
+                    .send(GetDocumentResponse::Links(LinksError::Cycle(links_path)))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::LOOP_DETECTED);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Links cycle detected","status":508,"#,
+                    r#""detail":"`somecoll/someid` has already been visited","#,
+                    r#""linksPath":["somecoll/someid","somecoll/other","somecoll/someid"]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn links_too_deep_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let links_path = vec![DocumentLocation::new(request.collection, request.id)];
+                response_tx
+                    .send(GetDocumentResponse::Links(LinksError::TooDeep(links_path)))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+        }
+
+        #[tokio::test]
+        async fn interpolation_cycle_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let path = vec!["${site.a}".to_string(), "${site.a}".to_string()];
+                response_tx
+                    .send(GetDocumentResponse::Interpolation(
+                        InterpolationError::Cycle(path),
+                    ))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::LOOP_DETECTED);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Placeholders cycle detected","status":508,"#,
+                    r#""detail":"`${site.a}` is already being resolved","#,
+                    r#""placeholdersPath":["${site.a}","${site.a}"]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn forbidden_link_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert!(request.access.allows(&request.collection));
+                let links_path = vec![
+                    DocumentLocation::new(request.collection, request.id),
+                    DocumentLocation::new("secrets", "token"),
+                ];
+                response_tx
+                    .send(GetDocumentResponse::Links(LinksError::Forbidden(
+                        links_path,
+                    )))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Link not authorized","status":403,"#,
+                    r#""detail":"`secrets/token` is in a collection which is not readable","#,
+                    r#""linksPath":["somecoll/someid","secrets/token"]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn forbidden_placeholder_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetDocumentResponse::Interpolation(
+                        InterpolationError::Forbidden("${ref:secrets/token#/value}".to_string()),
+                    ))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Placeholder not authorized","status":403,"#,
+                    r#""detail":"`${ref:secrets/token#/value}` refers to a collection which "#,
+                    r#"is not readable","placeholder":"${ref:secrets/token#/value}"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn unresolved_placeholder_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetDocumentResponse::Interpolation(
+                        InterpolationError::Unresolved("${site.code}".to_string()),
+                    ))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+        }
+    }
+
+    mod patch_config_handler {
+        use super::*;
+
+        fn testing_fixture(patch_config_channel: PatchConfigChannel) -> (Router, Request<Body>) {
+            testing_fixture_with_body(
+                patch_config_channel,
+                "application/json",
+                r#"{"somekey":42,"otherkey":["a","b"]}"#,
+            )
+        }
+
+        fn testing_fixture_with_body(
+            patch_config_channel: PatchConfigChannel,
+            content_type: &str,
+            body: &'static str,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                patch_config_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .method("PATCH")
+                .uri("/config/somecoll/someid")
+                .header("Content-Type", content_type)
+                .body(Body::from(body))
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn roundtrip_error() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        #[tokio::test]
+        async fn status_code_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(PatchConfigResponse::Status(StatusCode::IM_A_TEAPOT))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+            assert!(res.headers().get("X-Patched-Document").is_none());
+        }
+
+        #[tokio::test]
+        async fn patched_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let links_path = vec![
+                    DocumentLocation::new("somecoll", "someid"),
+                    DocumentLocation::new("othercoll", "otherid"),
+                ];
+                response_tx
+                    .send(PatchConfigResponse::Patched(links_path))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["X-Patched-Document"], "otherid");
+            assert_eq!(
+                res.headers()["X-Links-Path"],
+                "somecoll/someid, othercoll/otherid"
+            );
+        }
+
+        #[tokio::test]
+        async fn schema_violations_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let violations = vec![SchemaViolation {
+                    path: "/somekey".into(),
+                    keyword: "type".into(),
+                    message: "value is not of type \"string\"".into(),
+                }];
+                response_tx
+                    .send(PatchConfigResponse::Invalid(violations))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Schema validation failed","status":422,"#,
+                    r#""detail":"patched document violates 1 schema keyword(s)","#,
+                    r#""violations":[{"path":"/somekey","keyword":"type","#,
+                    r#""message":"value is not of type \"string\""}]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn forbidden_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let denied_fields = vec!["otherkey".into(), "somekey".into()];
+                response_tx
+                    .send(PatchConfigResponse::Forbidden(denied_fields))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Patch not authorized","status":403,"#,
+                    r#""detail":"the patch changes field(s) not granted to the caller: "#,
+                    r#"otherkey, somekey","deniedFields":["otherkey","somekey"]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn reserved_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(PatchConfigResponse::Reserved("_authorization".into()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Reserved document","status":403,"#,
+                    r#""detail":"the `_authorization` document is reserved, and can not be patched"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn unreadable_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(PatchConfigResponse::Unreadable(vec!["/secret".into()]))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Patch not authorized","status":403,"#,
+                    r#""detail":"the patch reads value(s) not readable by the caller: /secret","#,
+                    r#""unreadablePaths":["/secret"]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn unsupported_content_type() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture_with_body(tx, "text/plain", "somekey=42");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        }
+
+        #[tokio::test]
+        async fn invalid_body() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/json-patch+json",
+                r#"[{"op":"add","path":"somekey","value":1}]"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        #[tokio::test]
+        async fn mixed_fields_and_operators() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/json",
+                r#"{"somekey":42,"$inc":{"otherkey":1}}"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        #[tokio::test]
+        async fn operators_patch() {
+            let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert!(matches!(request.patch, Patch::Operators(_)));
+                response_tx
+                    .send(PatchConfigResponse::Status(StatusCode::OK))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/json",
+                r#"{"$inc":{"somekey":1},"$addToSet":{"otherkey":"c"}}"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn fields_patch() {
+            let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let Patch::Fields(changes) = request.patch else {
+                    panic!("unexpected patch kind");
+                };
+                assert_eq!(changes["somekey"], Bson::Null);
+                response_tx
+                    .send(PatchConfigResponse::Status(StatusCode::OK))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/json; charset=utf-8",
+                r#"{"somekey":null}"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn merge_patch() {
+            let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let Patch::Merge(changes) = request.patch else {
+                    panic!("unexpected patch kind");
+                };
+                assert_eq!(changes, doc! { "somekey": null, "otherkey": { "a": 3 } });
+                response_tx
+                    .send(PatchConfigResponse::Status(StatusCode::OK))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/merge-patch+json",
+                r#"{"somekey":null,"otherkey":{"a":3}}"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn json_patch() {
+            let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                let Patch::Json(operations) = request.patch else {
+                    panic!("unexpected patch kind");
+                };
+                assert_eq!(operations.len(), 2);
+                response_tx
+                    .send(PatchConfigResponse::Status(StatusCode::OK))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture_with_body(
+                tx,
+                "application/json-patch+json",
+                r#"[{"op":"test","path":"/a","value":1},{"op":"remove","path":"/b/0"}]"#,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+
+    mod delete_document_handler {
+        use super::*;
+
+        fn testing_fixture(
+            delete_document_channel: DeleteDocumentChannel,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                delete_document_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .method("DELETE")
+                .uri("/config/somecoll/someid")
+                .header("X-Caller", "someone")
+                .header("X-Request-Id", "somerequest")
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn roundtrip_error() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        #[tokio::test]
+        async fn status_code_response() {
+            let (tx, mut rx) =
+                roundtrip_channel::<DeleteDocumentRequest, DeleteDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.id, "someid");
+                let context = WriteContext {
+                    caller: None,
+                    claimed_caller: Some("someone".into()),
+                    roles: Vec::new(),
+                    request_id: "somerequest".into(),
+                };
+                assert_eq!(request.context, context);
+                response_tx
+                    .send(DeleteDocumentResponse::Status(StatusCode::NO_CONTENT))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        }
+
+        #[tokio::test]
+        async fn forbidden_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(DeleteDocumentResponse::Forbidden)
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Delete not authorized","status":403,"#,
+                    r#""detail":"the deletion of the document is not granted to the caller"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn history_failed() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                let failure = HistoryFailure { applied: true };
+                response_tx
+                    .send(DeleteDocumentResponse::HistoryFailed(failure))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"History recording failed","status":500,"#,
+                    r#""detail":"the write has been applied, but its history entry could not be recorded","#,
+                    r#""applied":true}"#
+                )
+            );
+        }
+    }
+
+    mod get_schema_handler {
+        use super::*;
+
+        fn testing_fixture(get_schema_channel: GetSchemaChannel) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                get_schema_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri("/config/somecoll/_schema")
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn roundtrip_error() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        #[tokio::test]
+        async fn schema_response() {
+            let (tx, mut rx) = roundtrip_channel::<String, GetSchemaResponse>(1);
+            tokio::spawn(async move {
+                let (collection, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(collection, "somecoll");
+                response_tx
+                    .send(GetSchemaResponse::Schema(doc! { "type": "object" }))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(body, r#"{"type":"object"}"#);
+        }
+
+        #[tokio::test]
+        async fn not_found_response() {
+            let (tx, mut rx) = roundtrip_channel::<String, GetSchemaResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetSchemaResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    mod put_schema_handler {
+        use super::*;
+
+        fn testing_fixture(
+            put_schema_channel: PutSchemaChannel,
+            body: &'static str,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                put_schema_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .method("PUT")
+                .uri("/config/somecoll/_schema")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn invalid_body() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx, "[]");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        #[tokio::test]
+        async fn status_code_response() {
+            let (tx, mut rx) = roundtrip_channel::<PutSchemaRequest, PutSchemaResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.schema, doc! { "type": "object" });
+                assert_eq!(request.context.caller, None);
+                assert!(!request.context.request_id.is_empty());
+                response_tx
+                    .send(PutSchemaResponse::Status(StatusCode::CREATED))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, r#"{"type":"object"}"#);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::CREATED);
+        }
+
+        #[tokio::test]
+        async fn invalid_schema_response() {
+            let (tx, mut rx) = roundtrip_channel::<PutSchemaRequest, PutSchemaResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(PutSchemaResponse::Invalid("some detail".into()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, r#"{"type":"unknown"}"#);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+        }
+
+        #[tokio::test]
+        async fn forbidden_response() {
+            let (tx, mut rx) = roundtrip_channel::<PutSchemaRequest, PutSchemaResponse>(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(PutSchemaResponse::Forbidden)
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, r#"{"type":"object"}"#);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Schema update not authorized","status":403,"#,
+                    r#""detail":"the schema update is not granted to the caller"}"#
+                )
+            );
+        }
+    }
+
+    mod validate_collection_handler {
+        use super::*;
+
+        fn testing_fixture(
+            validate_collection_channel: ValidateCollectionChannel,
+            body: &'static str,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                validate_collection_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .method("POST")
+                .uri("/config/somecoll/_validate")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn stored_schema() {
+            let (tx, mut rx) =
+                roundtrip_channel::<ValidateCollectionRequest, ValidateCollectionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert!(request.schema.is_none());
+                response_tx
+                    .send(ValidateCollectionResponse::Violations(Vec::new()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, "");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(body, r#"{"valid":true,"documents":[]}"#);
+        }
+
+        #[tokio::test]
+        async fn candidate_schema() {
+            let (tx, mut rx) =
+                roundtrip_channel::<ValidateCollectionRequest, ValidateCollectionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.schema, Some(doc! { "required": ["a"] }));
+                let documents = vec![DocumentViolations {
+                    id: "someid".into(),
+                    violations: vec![SchemaViolation {
+                        path: String::new(),
+                        keyword: "required".into(),
+                        message: "\"a\" is a required property".into(),
+                    }],
+                }];
+                response_tx
+                    .send(ValidateCollectionResponse::Violations(documents))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, r#"{"required":["a"]}"#);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"valid":false,"documents":[{"id":"someid","violations":"#,
+                    r#"[{"path":"","keyword":"required","#,
+                    r#""message":"\"a\" is a required property"}]}]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn not_found_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(ValidateCollectionResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, "");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    mod get_history_handler {
+        use super::*;
+
+        fn testing_fixture(
+            get_history_channel: GetHistoryChannel,
+            query: &str,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                get_history_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri(format!("/config/somecoll/someid/history{query}"))
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn roundtrip_error() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx, "");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        #[tokio::test]
+        async fn revisions_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetHistoryRequest, GetHistoryResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.id, "someid");
+                assert_eq!(request.skip, 2);
+                assert_eq!(request.limit, 1);
+                let revisions = vec![doc! { "revision": 1_i64, "operation": "patch" }];
+                response_tx
+                    .send(GetHistoryResponse::Revisions {
+                        revisions,
+                        total: 3,
+                    })
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, "?skip=2&limit=1");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                r#"{"total":3,"revisions":[{"revision":1,"operation":"patch"}]}"#
+            );
+        }
+
+        #[tokio::test]
+        async fn invalid_limit() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx, "?limit=0");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+
+        #[tokio::test]
+        async fn not_found_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetHistoryResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx, "");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    mod get_revision_handler {
+        use super::*;
+
+        fn testing_fixture(get_revision_channel: GetRevisionChannel) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                get_revision_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri("/config/somecoll/someid/history/2")
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn document_response() {
+            let (tx, mut rx) = roundtrip_channel::<GetRevisionRequest, GetRevisionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.id, "someid");
+                assert_eq!(request.revision, 2);
+                response_tx
+                    .send(GetRevisionResponse::Document(doc! { "_id": "someid" }))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(body, r#"{"_id":"someid"}"#);
+        }
+
+        #[tokio::test]
+        async fn deleted_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetRevisionResponse::Deleted(String::new()))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::GONE);
+        }
+    }
+
+    mod restore_revision_handler {
+        use super::*;
+
+        fn testing_fixture(
+            get_revision_channel: GetRevisionChannel,
+            get_document_channel: GetDocumentChannel,
+            patch_config_channel: PatchConfigChannel,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                get_document_channel,
+                patch_config_channel,
+                get_revision_channel,
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .method("POST")
+                .uri("/config/somecoll/someid/history/1/restore")
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        fn revision_channel(previous: Document) -> GetRevisionChannel {
+            let (tx, mut rx) = roundtrip_channel::<GetRevisionRequest, GetRevisionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.revision, 1);
+                response_tx
+                    .send(GetRevisionResponse::Document(previous))
+                    .expect("error sending response");
+            });
+            tx
+        }
+
+        fn document_channel(current: Document) -> GetDocumentChannel {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert!(!request.resolve);
+                assert!(!request.interpolate);
+                response_tx
+                    .send(GetDocumentResponse::Document {
+                        document: current,
+                        links_path: vec![],
+                    })
+                    .expect("error sending response");
+            });
+            tx
+        }
+
+        #[tokio::test]
+        async fn patched_response() {
+            let (tx, mut rx) = roundtrip_channel::<PatchConfigRequest, PatchConfigResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.id, "someid");
+                let Patch::Json(operations) = request.patch else {
+                    panic!("restore patch is a JSON Patch");
+                };
+                assert_eq!(operations.len(), 2);
+                let links_path = vec![DocumentLocation::new("somecoll", "someid")];
+                response_tx
+                    .send(PatchConfigResponse::Patched(links_path))
+                    .expect("error sending response");
+            });
+            let (app, req) = testing_fixture(
+                revision_channel(doc! { "_id": "someid", "a": 1 }),
+                document_channel(doc! { "_id": "someid", "a": 2 }),
+                tx,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()["X-Patched-Document"], "someid");
+        }
+
+        #[tokio::test]
+        async fn unchanged_response() {
+            let (tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(
+                revision_channel(doc! { "_id": "someid", "a": 1 }),
+                document_channel(doc! { "_id": "someid", "a": 1 }),
+                tx,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        }
+
+        #[tokio::test]
+        async fn reserved_document() {
+            let (revision_tx, _) = roundtrip_channel(1);
+            let (document_tx, _) = roundtrip_channel(1);
+            let (patch_tx, _) = roundtrip_channel(1);
+            let app = app(AppState {
+                get_document_channel: document_tx,
+                patch_config_channel: patch_tx,
+                get_revision_channel: revision_tx,
+                ..AppState::default()
+            });
+            for id in ["_schema", "_authorization"] {
+                let req = Request::builder()
+                    .method("POST")
+                    .uri(format!("/config/somecoll/{id}/history/1/restore"))
+                    .body(Body::empty())
+                    .unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::FORBIDDEN, "{id}");
+            }
+        }
+
+        #[tokio::test]
+        async fn deleted_document() {
+            let (document_tx, mut document_rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = document_rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetDocumentResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (patch_tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(
+                revision_channel(doc! { "_id": "someid", "a": 1 }),
+                document_tx,
+                patch_tx,
+            );
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Document not found","status":404,"#,
+                    r#""detail":"document `someid` does not exist, "#,
+                    r#"deleted documents can not be restored"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn revision_not_found() {
+            let (revision_tx, mut revision_rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = revision_rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetRevisionResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (document_tx, _) = roundtrip_channel(1);
+            let (patch_tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(revision_tx, document_tx, patch_tx);
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    mod diff_handler {
+        use super::*;
+
+        fn testing_fixture(
+            get_document_channel: GetDocumentChannel,
+            get_revision_channel: GetRevisionChannel,
+            query: &str,
+        ) -> (Router, Request<Body>) {
+            let app = app(AppState {
+                get_document_channel,
+                get_revision_channel,
+                collection_filter: CollectionFilter::new(&[], &["secrets".into()]).into(),
+                ..AppState::default()
+            });
+            let req = Request::builder()
+                .uri(format!("/config/somecoll/_diff?{query}"))
+                .body(Body::empty())
+                .unwrap();
+            (app, req)
+        }
+
+        #[tokio::test]
+        async fn hidden_collection() {
+            let (tx, _) = roundtrip_channel(1);
+            let (revision_tx, _) = roundtrip_channel(1);
+            let (app, req) =
+                testing_fixture(tx, revision_tx, "left=a&right=b&rightCollection=secrets");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let req = Request::builder()
+                .uri("/config/secrets/_diff?left=a&right=b")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Access forbidden","status":403,"#,
+                    r#""detail":"`secrets` collection is not readable"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn documents_diff() {
+            let (tx, mut rx) = roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(2);
+            tokio::spawn(async move {
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "somecoll");
+                assert_eq!(request.id, "a");
+                assert!(request.resolve);
+                response_tx
+                    .send(GetDocumentResponse::Document {
+                        document: doc! { "_id": "a", "x": 1, "y": 2 },
+                        links_path: vec![],
+                    })
+                    .expect("error sending response");
+                let (request, response_tx) = rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "othercoll");
+                assert_eq!(request.id, "b");
+                response_tx
+                    .send(GetDocumentResponse::Document {
+                        document: doc! { "_id": "b", "x": 1, "z": 3 },
+                        links_path: vec![],
+                    })
+                    .expect("error sending response");
+            });
+            let (revision_tx, _) = roundtrip_channel(1);
+            let (app, req) =
+                testing_fixture(tx, revision_tx, "left=a&right=b&rightCollection=othercoll");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"added":[{"path":"/z","value":3}],"#,
+                    r#""removed":[{"path":"/y","value":2}],"changed":[]}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn revision_diff() {
+            let (document_tx, mut document_rx) =
+                roundtrip_channel::<GetDocumentRequest, GetDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) =
+                    document_rx.recv().await.expect("channel has been closed");
+                assert!(!request.resolve);
+                assert!(!request.interpolate);
+                response_tx
+                    .send(GetDocumentResponse::Document {
+                        document: doc! { "_id": "a", "x": 2 },
+                        links_path: vec![],
+                    })
+                    .expect("error sending response");
+            });
+            let (revision_tx, mut revision_rx) =
+                roundtrip_channel::<GetRevisionRequest, GetRevisionResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) =
+                    revision_rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.id, "a");
+                assert_eq!(request.revision, 3);
+                response_tx
+                    .send(GetRevisionResponse::Document(doc! { "_id": "a", "x": 1 }))
+                    .expect("error sending response");
+            });
+            let (app, req) =
+                testing_fixture(document_tx, revision_tx, "left=a&leftRevision=3&right=a");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                r#"{"added":[],"removed":[],"changed":[{"path":"/x","left":1,"right":2}]}"#
+            );
+        }
+
+        #[tokio::test]
+        async fn not_found_response() {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (_, response_tx) = rx.recv().await.expect("channel has been closed");
+                response_tx
+                    .send(GetDocumentResponse::NotFound(String::new()))
+                    .expect("error sending response");
+            });
+            let (revision_tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx, revision_tx, "left=a&right=b");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        }
+
+        #[tokio::test]
+        async fn missing_parameter() {
+            let (tx, _) = roundtrip_channel(1);
+            let (revision_tx, _) = roundtrip_channel(1);
+            let (app, req) = testing_fixture(tx, revision_tx, "left=a");
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    mod authenticate {
+        use super::*;
+
+        fn testing_fixture(
+            find_api_key_channel: FindApiKeyChannel,
+            delete_document_channel: DeleteDocumentChannel,
+        ) -> Router {
+            let (health_channel, mut health_rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                if let Some(((), response_tx)) = health_rx.recv().await {
+                    response_tx.send(true).expect("error sending response");
+                }
+            });
+            app(AppState {
+                health_channel,
+                delete_document_channel,
+                authentication: Authentication {
+                    api_keys: ApiKeys::Collection {
+                        collection: "apiKeys".into(),
+                        channel: find_api_key_channel,
+                    },
+                    jwt: None,
+                    certificates: Some(Default::default()),
+                }
+                .into(),
+                ..AppState::default()
+            })
+        }
+
+        fn delete_request(key: Option<&str>) -> Request<Body> {
+            let mut builder = Request::builder()
+                .method("DELETE")
+                .uri("/config/somecoll/someid")
+                .header("X-Caller", "spoofed");
+            if let Some(key) = key {
+                builder = builder.header("X-Api-Key", key);
+            }
+            builder.body(Body::empty()).unwrap()
+        }
+
+        fn key_channel(key: Option<Document>) -> FindApiKeyChannel {
+            let (tx, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                let (request, response_tx): (FindApiKeyRequest, _) =
+                    rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.collection, "apiKeys");
+                assert_eq!(request.hash, hash_key("somekey"));
+                response_tx.send(key).expect("error sending response");
+            });
+            tx
+        }
+
+        #[tokio::test]
+        async fn health_not_authenticated() {
+            let (key_tx, _) = roundtrip_channel(1);
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_tx, delete_tx);
+            let req = Request::builder()
+                .uri("/health")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        }
+
+        #[tokio::test]
+        async fn missing_key() {
+            let (key_tx, _) = roundtrip_channel(1);
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_tx, delete_tx);
+            let res = app.oneshot(delete_request(None)).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+        }
+
+        #[tokio::test]
+        async fn unknown_key() {
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_channel(None), delete_tx);
+            let res = app.oneshot(delete_request(Some("somekey"))).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        #[tokio::test]
+        async fn invalid_token() {
+            let (key_tx, _) = roundtrip_channel(1);
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_tx, delete_tx);
+            let req = Request::builder()
+                .method("DELETE")
+                .uri("/config/somecoll/someid")
+                .header("Authorization", "Bearer sometoken")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Invalid token","status":401,"#,
+                    r#""detail":"bearer tokens are not accepted"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn unknown_certificate() {
+            let (key_tx, _) = roundtrip_channel(1);
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_tx, delete_tx);
+            let mut req = delete_request(None);
+            req.extensions_mut().insert(ConnectInfo(TlsClient {
+                addr: ([127, 0, 0, 1], 1234).into(),
+                certificate_subject: Some("CN=intruder".into()),
+            }));
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Unknown client certificate","status":401,"#,
+                    r#""detail":"no principal is mapped to the `CN=intruder` certificate subject"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn forbidden() {
+            let key = doc! { "name": "reader", "hash": "", "scopes": ["read"] };
+            let (delete_tx, _) = roundtrip_channel(1);
+            let app = testing_fixture(key_channel(Some(key)), delete_tx);
+            let res = app.oneshot(delete_request(Some("somekey"))).await.unwrap();
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Access forbidden","status":403,"#,
+                    r#""detail":"`reader` key has no write scope"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn internal_collection() {
+            let app = app(AppState {
+                collection_filter: CollectionFilter::default()
+                    .with_internal(vec!["apiKeys".into(), "history".into()])
+                    .into(),
+                ..AppState::default()
+            });
+            for uri in ["/config/apiKeys", "/config/history/someid"] {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::FORBIDDEN, "{uri}");
+            }
+        }
+
+        #[tokio::test]
+        async fn admin_scope() {
+            for (scopes, status) in [
+                (vec!["read", "write"], StatusCode::FORBIDDEN),
+                (vec!["admin"], StatusCode::OK),
+            ] {
+                let key = doc! { "name": "operator", "hash": "", "scopes": scopes };
+                let (delete_tx, _) = roundtrip_channel(1);
+                let app = testing_fixture(key_channel(Some(key)), delete_tx);
+                let req = Request::builder()
+                    .uri("/admin/log-filter")
+                    .header("X-Api-Key", "somekey")
+                    .body(Body::empty())
+                    .unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(res.status(), status);
+            }
+        }
+
+        #[tokio::test]
+        async fn admin_disabled() {
+            let app = app(AppState {
+                authentication: Authentication::default().into(),
+                ..AppState::default()
+            });
+            for method in ["GET", "PUT", "DELETE"] {
+                let req = Request::builder()
+                    .method(method)
+                    .uri("/admin/log-filter")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"filter":"debug"}"#))
+                    .unwrap();
+                let res = app.clone().oneshot(req).await.unwrap();
+                assert_eq!(res.status(), StatusCode::FORBIDDEN, "{method}");
+            }
+        }
+
+        #[tokio::test]
+        async fn schema_update_admin_scope() {
+            for (scopes, status) in [
+                (vec!["read", "write"], StatusCode::FORBIDDEN),
+                // Authorized, the schema channel being closed.
+                (vec!["admin"], StatusCode::INTERNAL_SERVER_ERROR),
+            ] {
+                let key = doc! { "name": "operator", "hash": "", "scopes": scopes };
+                let (delete_tx, _) = roundtrip_channel(1);
+                let app = testing_fixture(key_channel(Some(key)), delete_tx);
+                let req = Request::builder()
+                    .method("PUT")
+                    .uri("/config/somecoll/_schema")
+                    .header("X-Api-Key", "somekey")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from("{}"))
+                    .unwrap();
+                let res = app.oneshot(req).await.unwrap();
+                assert_eq!(res.status(), status);
+            }
+        }
+
+        #[tokio::test]
+        async fn authenticated() {
+            let key = doc! {
+                "name": "writer",
+                "hash": "",
+                "scopes": ["write"],
+                "collections": ["somecoll"],
+            };
+            let (delete_tx, mut delete_rx) =
+                roundtrip_channel::<DeleteDocumentRequest, DeleteDocumentResponse>(1);
+            tokio::spawn(async move {
+                let (request, response_tx) =
+                    delete_rx.recv().await.expect("channel has been closed");
+                assert_eq!(request.context.caller.as_deref(), Some("writer"));
+                assert_eq!(request.context.claimed_caller, None);
+                response_tx
+                    .send(DeleteDocumentResponse::Status(StatusCode::NO_CONTENT))
+                    .expect("error sending response");
+            });
+            let app = testing_fixture(key_channel(Some(key)), delete_tx);
+            let res = app.oneshot(delete_request(Some("somekey"))).await.unwrap();
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        }
+    }
+
+    mod check_exposure {
+        use super::*;
+
+        fn testing_fixture() -> Router {
+            app(AppState {
+                exposure: Exposure::new(&["machines".into(), "line-*".into()], true),
+                ..AppState::default()
+            })
+        }
+
+        fn request(method: &str, uri: &str) -> Request<Body> {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn not_exposed() {
+            let res = testing_fixture()
+                .oneshot(request("GET", "/config/secrets/someid"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::NOT_FOUND);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Collection not exposed","status":404,"#,
+                    r#""detail":"`secrets` collection is not exposed by this service"}"#
+                )
+            );
+        }
+
+        #[tokio::test]
+        async fn read_only() {
+            let res = testing_fixture()
+                .oneshot(request("PATCH", "/config/line-1/someid"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(res.headers()["Allow"], "GET");
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(
+                body,
+                concat!(
+                    r#"{"title":"Read-only service","status":405,"#,
+                    r#""detail":"`PATCH` requests are not allowed, this service is read-only"}"#
+                )
+            );
+            let res = testing_fixture()
+                .oneshot(request("POST", "/config/machines/someid/history/1/restore"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(res.headers()["Allow"], "");
+        }
+
+        #[tokio::test]
+        async fn reads_allowed() {
+            // The request reaches the handler, whose channel has been closed.
+            let res = testing_fixture()
+                .oneshot(request("POST", "/config/machines/_validate"))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    mod log_access {
+        use crate::logging::tests::Buffer;
+
+        use super::*;
+
+        fn testing_fixture() -> Router {
+            app(AppState {
+                access_log: AccessLog::new(1.0, &["/health".into()]),
+                ..AppState::default()
+            })
+        }
+
+        #[tokio::test]
+        async fn logged() {
+            let buffer = Buffer::default();
+            let subscriber = tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(buffer.clone())
+                .finish();
+            let _default = tracing::subscriber::set_default(subscriber);
+            for uri in ["/config/machines/someid", "/health"] {
+                let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+                testing_fixture().oneshot(req).await.unwrap();
+            }
+            let output = buffer.contents();
+            let lines = output
+                .lines()
+                .filter(|line| line.contains("access_log:"))
+                .collect::<Vec<_>>();
+            assert_eq!(lines.len(), 1, "{output}");
+            for field in [
+                "method=GET",
+                r#"route="/config/{collection}/{id}""#,
+                r#"collection="machines""#,
+                r#"id="someid""#,
+                "status=500",
+                "bytes=",
+            ] {
+                assert!(lines[0].contains(field), "{field} not in {}", lines[0]);
+            }
+            assert!(!lines[0].contains("principal="));
+        }
+    }
+
+    mod put_log_filter_handler {
+        use crate::logging::tests::{Buffer, filtered_subscriber};
+
+        use super::*;
+
+        fn testing_fixture(log_filter: LogFilter) -> Router {
+            let (channel, mut rx) = roundtrip_channel(1);
+            tokio::spawn(async move {
+                while let Some((_, response_tx)) = rx.recv().await {
+                    let key = doc! { "name": "operator", "hash": "", "scopes": ["admin"] };
+                    response_tx.send(Some(key)).expect("error sending response");
+                }
+            });
+            app(AppState {
+                exposure: Exposure::new(&[], true),
+                authentication: Authentication {
+                    api_keys: ApiKeys::Collection {
+                        collection: "apiKeys".into(),
+                        channel,
+                    },
+                    jwt: None,
+                    certificates: None,
+                }
+                .into(),
+                log_filter,
+                ..AppState::default()
+            })
+        }
+
+        fn put_request(body: &'static str) -> Request<Body> {
+            Request::builder()
+                .method("PUT")
+                .uri("/admin/log-filter")
+                .header("X-Api-Key", "somekey")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn temporary() {
+            let (subscriber, log_filter) = filtered_subscriber(Buffer::default());
+            let _default = tracing::subscriber::set_default(subscriber);
+            // Not prevented by the read-only mode.
+            let app = testing_fixture(log_filter);
+            let res = app
+                .clone()
+                .oneshot(put_request(
+                    r#"{"filter":"config_api::db=debug","duration":60}"#,
+                ))
+                .await
+                .unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            let status = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+            assert_eq!(status["filter"], "config_api::db=debug,info");
+            assert!(
+                status["revertIn"]
+                    .as_u64()
+                    .is_some_and(|seconds| seconds > 50)
+            );
+            let req = Request::builder()
+                .method("DELETE")
+                .uri("/admin/log-filter")
+                .header("X-Api-Key", "somekey")
+                .body(Body::empty())
+                .unwrap();
+            let res = app.oneshot(req).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = to_bytes(res.into_body(), 1024).await.unwrap();
+            assert_eq!(body, r#"{"filter":"info","revertIn":null}"#);
+        }
+
+        #[tokio::test]
+        async fn invalid() {
+            for (body, detail) in [
+                (
+                    r#"{"filter":"config_api::db=loud"}"#,
+                    concat!(
+                        r#"error parsing level filter: expected one of "off", "error", "#,
+                        r#""warn", "info", "debug", "trace", or a number 0-5"#
+                    ),
+                ),
+                (
+                    r#"{"filter":"debug","duration":0}"#,
+                    "duration must be between 1 and 86400 seconds",
+                ),
+            ] {
+                let res = testing_fixture(LogFilter::default())
+                    .oneshot(put_request(body))
+                    .await
+                    .unwrap();
+                assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+                let body = to_bytes(res.into_body(), 1024).await.unwrap();
+                let problem = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+                assert_eq!(problem["detail"], detail);
+            }
         }
     }
 }
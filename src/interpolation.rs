@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use mongodb::bson::{Bson, Document};
+
+use crate::authorization::ReadAccess;
+use crate::links::DocumentLocation;
+use crate::patch::JsonPointer;
+
+/// Part of a string value.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Reference to a value of another document, written `${id.path}` for a variable or
+/// `${ref:collection/id#/pointer}` for any document.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Placeholder {
+    text: String,
+    pub(crate) location: DocumentLocation,
+    pub(crate) pointer: JsonPointer,
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum InterpolationError {
+    /// A string value can not be interpolated.
+    Invalid(String),
+    /// The placeholder refers to a value which does not exist.
+    Unresolved(String),
+    /// The last placeholder of the path is already being resolved.
+    Cycle(Vec<String>),
+    /// The placeholder refers to a collection the caller can not read.
+    Forbidden(String),
+    Database(mongodb::error::Error),
+}
+
+impl From<mongodb::error::Error> for InterpolationError {
+    fn from(err: mongodb::error::Error) -> Self {
+        Self::Database(err)
+    }
+}
+
+/// State shared while interpolating the values of a request.
+#[derive(Debug, Default)]
+pub(crate) struct InterpolationContext {
+    /// Collections the placeholders may refer to.
+    pub(crate) access: ReadAccess,
+    /// Placeholders being resolved.
+    stack: Vec<String>,
+    /// Documents already fetched, by location, without the fields callers can not read.
+    pub(crate) documents: HashMap<String, Option<Document>>,
+}
+
+impl InterpolationContext {
+    pub(crate) fn new(access: ReadAccess) -> Self {
+        Self {
+            access,
+            ..Self::default()
+        }
+    }
+
+    /// Marks a placeholder as being resolved, failing if it already is.
+    pub(crate) fn enter(&mut self, placeholder: &Placeholder) -> Result<(), InterpolationError> {
+        let text = placeholder.to_string();
+        let cycle = self.stack.contains(&text);
+        self.stack.push(text);
+        if cycle {
+            return Err(InterpolationError::Cycle(std::mem::take(&mut self.stack)));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.stack.pop();
+    }
+}
+
+/// Splits a string value into literals and placeholders.
+///
+/// Variables are looked up in `variables_collection`, and `$${` is an escaped `${`.
+pub(crate) fn parse_template(
+    value: &str,
+    variables_collection: &str,
+) -> Result<Vec<Segment>, InterpolationError> {
+    let mut segments = Vec::new();
+    let mut literal = String::new();
+    let mut rest = value;
+    while let Some(start) = rest.find("${") {
+        if let Some(escaped) = rest[..start].strip_suffix('$') {
+            literal.push_str(escaped);
+            literal.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        literal.push_str(&rest[..start]);
+        let Some(length) = rest[start..].find('}') else {
+            return Err(InterpolationError::Invalid(format!(
+                "unterminated placeholder in `{value}`"
+            )));
+        };
+        let text = &rest[start..=start + length];
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+        segments.push(Segment::Placeholder(parse_placeholder(
+            text,
+            variables_collection,
+        )?));
+        rest = &rest[start + length + 1..];
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() || segments.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+fn parse_placeholder(
+    text: &str,
+    variables_collection: &str,
+) -> Result<Placeholder, InterpolationError> {
+    let invalid = || InterpolationError::Invalid(format!("invalid placeholder `{text}`"));
+    let content = &text[2..text.len() - 1];
+    let (location, pointer) = match content.strip_prefix("ref:") {
+        Some(reference) => {
+            let (target, pointer) = reference.split_once('#').unwrap_or((reference, ""));
+            let (collection, id) = target.split_once('/').ok_or_else(invalid)?;
+            if collection.is_empty() || id.is_empty() {
+                return Err(invalid());
+            }
+            let pointer = JsonPointer::try_from(pointer.to_owned())
+                .map_err(|err| InterpolationError::Invalid(format!("{err} in `{text}`")))?;
+            (DocumentLocation::new(collection, id), pointer)
+        }
+        None => {
+            let mut tokens = content.split('.').map(str::to_owned).collect::<Vec<_>>();
+            if tokens.iter().any(String::is_empty) {
+                return Err(invalid());
+            }
+            let id = tokens.remove(0);
+            (
+                DocumentLocation::new(variables_collection, id),
+                JsonPointer::new(tokens),
+            )
+        }
+    };
+    Ok(Placeholder {
+        text: text.to_owned(),
+        location,
+        pointer,
+    })
+}
+
+/// Builds the interpolated value from the segments of a string and the values of its
+/// placeholders.
+///
+/// A string made of a single placeholder takes the value of the placeholder as is, otherwise
+/// values are embedded as text.
+pub(crate) fn render(segments: &[Segment], values: Vec<Bson>) -> Result<Bson, InterpolationError> {
+    let mut values = values.into_iter();
+    if let [Segment::Placeholder(_)] = segments {
+        return Ok(values.next().unwrap_or(Bson::Null));
+    }
+    let mut rendered = String::new();
+    for segment in segments {
+        match segment {
+            Segment::Literal(literal) => rendered.push_str(literal),
+            Segment::Placeholder(placeholder) => match values.next() {
+                Some(Bson::String(value)) => rendered.push_str(&value),
+                Some(Bson::Int32(value)) => rendered.push_str(&value.to_string()),
+                Some(Bson::Int64(value)) => rendered.push_str(&value.to_string()),
+                Some(Bson::Double(value)) => rendered.push_str(&value.to_string()),
+                Some(Bson::Boolean(value)) => rendered.push_str(&value.to_string()),
+                _ => {
+                    return Err(InterpolationError::Invalid(format!(
+                        "value of `{placeholder}` can not be embedded in a string"
+                    )));
+                }
+            },
+        }
+    }
+    Ok(Bson::String(rendered))
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    fn placeholder(text: &str) -> Placeholder {
+        parse_placeholder(text, "variables").unwrap()
+    }
+
+    mod parse_template {
+        use super::*;
+
+        #[test]
+        fn literal() {
+            let segments = parse_template("plain $text {}", "variables").unwrap();
+            assert_eq!(segments, [Segment::Literal("plain $text {}".into())]);
+            let segments = parse_template("", "variables").unwrap();
+            assert_eq!(segments, [Segment::Literal(String::new())]);
+        }
+
+        #[test]
+        fn variable() {
+            let segments = parse_template("host-${site.code}.local", "variables").unwrap();
+            let [
+                Segment::Literal(prefix),
+                Segment::Placeholder(placeholder),
+                Segment::Literal(suffix),
+            ] = &segments[..]
+            else {
+                panic!("unexpected segments {segments:?}");
+            };
+            assert_eq!(prefix, "host-");
+            assert_eq!(suffix, ".local");
+            assert_eq!(placeholder.to_string(), "${site.code}");
+            assert_eq!(
+                placeholder.location,
+                DocumentLocation::new("variables", "site")
+            );
+            assert_eq!(placeholder.pointer.to_string(), "/code");
+        }
+
+        #[test]
+        fn reference() {
+            let segments = parse_template("${ref:coll/a/b#/c~1d/0}", "variables").unwrap();
+            let [Segment::Placeholder(placeholder)] = &segments[..] else {
+                panic!("unexpected segments {segments:?}");
+            };
+            assert_eq!(placeholder.location, DocumentLocation::new("coll", "a/b"));
+            assert_eq!(placeholder.pointer.to_string(), "/c~1d/0");
+            let whole = super::placeholder("${ref:coll/a}");
+            assert_eq!(whole.location, DocumentLocation::new("coll", "a"));
+            assert_eq!(whole.pointer.to_string(), "");
+        }
+
+        #[test]
+        fn escaping() {
+            let segments = parse_template("$${site.code} ${site.code}", "variables").unwrap();
+            let [Segment::Literal(literal), Segment::Placeholder(_)] = &segments[..] else {
+                panic!("unexpected segments {segments:?}");
+            };
+            assert_eq!(literal, "${site.code} ");
+        }
+
+        #[test]
+        fn invalid() {
+            for template in [
+                "${site.code",
+                "${}",
+                "${site..code}",
+                "${ref:coll}",
+                "${ref:/id}",
+                "${ref:coll/id#path}",
+            ] {
+                assert!(
+                    matches!(
+                        parse_template(template, "variables"),
+                        Err(InterpolationError::Invalid(_))
+                    ),
+                    "{template} should be invalid"
+                );
+            }
+        }
+    }
+
+    mod render {
+        use super::*;
+
+        #[test]
+        fn single_placeholder() {
+            let segments = [Segment::Placeholder(placeholder("${site.ports}"))];
+            let value = Bson::Array(vec![80.into(), 443.into()]);
+            assert_eq!(render(&segments, vec![value.clone()]).unwrap(), value);
+        }
+
+        #[test]
+        fn embedded() {
+            let segments = [
+                Segment::Literal("a".into()),
+                Segment::Placeholder(placeholder("${site.code}")),
+                Segment::Placeholder(placeholder("${site.port}")),
+                Segment::Placeholder(placeholder("${site.ratio}")),
+                Segment::Placeholder(placeholder("${site.enabled}")),
+            ];
+            let values = vec!["b".into(), 80.into(), 0.5.into(), true.into()];
+            let rendered = render(&segments, values).unwrap();
+            assert_eq!(rendered, Bson::String("ab800.5true".into()));
+        }
+
+        #[test]
+        fn not_embeddable() {
+            let segments = [
+                Segment::Literal("a".into()),
+                Segment::Placeholder(placeholder("${site.nested}")),
+            ];
+            let values = vec![doc! { "b": 1 }.into()];
+            assert!(matches!(
+                render(&segments, values),
+                Err(InterpolationError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let mut context = InterpolationContext::default();
+        let a = placeholder("${site.a}");
+        let b = placeholder("${site.b}");
+        context.enter(&a).unwrap();
+        context.enter(&b).unwrap();
+        context.leave();
+        context.enter(&b).unwrap();
+        let Err(InterpolationError::Cycle(path)) = context.enter(&a) else {
+            panic!("cycle should be detected");
+        };
+        assert_eq!(path, ["${site.a}", "${site.b}", "${site.a}"]);
+    }
+}
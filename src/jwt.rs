@@ -0,0 +1,440 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, bail};
+use clap::Args;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::{info, instrument, warn};
+
+#[derive(Args)]
+#[group(id = "jwt")]
+pub(crate) struct Config {
+    /// PEM files holding the public keys verifying JWT signatures, enabling JWT authentication
+    #[arg(env, long, value_delimiter = ',')]
+    jwt_public_keys: Vec<PathBuf>,
+
+    /// JWKS file holding the public keys verifying JWT signatures, enabling JWT authentication
+    #[arg(env, long)]
+    jwt_jwks_file: Option<PathBuf>,
+
+    /// Expected issuer (`iss` claim) of JWTs
+    #[arg(env, long)]
+    jwt_issuer: Option<String>,
+
+    /// Expected audience (`aud` claim) of JWTs
+    #[arg(env, long)]
+    jwt_audience: Option<String>,
+
+    /// Claim of JWTs holding the roles or groups of the caller
+    #[arg(env, long, default_value = "roles")]
+    jwt_roles_claim: String,
+
+    /// Claim of JWTs holding the scopes granted to the caller
+    #[arg(env, long, default_value = "scope")]
+    jwt_scopes_claim: String,
+
+    /// Tolerance in seconds when checking the expiry and validity dates of JWTs
+    #[arg(env, long, default_value_t = 60)]
+    jwt_leeway: u64,
+}
+
+/// Verified claims of a JWT.
+pub(crate) type Claims = serde_json::Map<String, Value>;
+
+const RSA_ALGORITHMS: &[Algorithm] = &[Algorithm::RS256, Algorithm::RS384, Algorithm::RS512];
+
+#[derive(Clone)]
+struct VerificationKey {
+    id: Option<String>,
+    key: DecodingKey,
+    /// Signature algorithms the key is used with.
+    algorithms: &'static [Algorithm],
+}
+
+impl VerificationKey {
+    /// Reads a JWK, returning `None` if it is not a RSA, P-256 or P-384 public key.
+    fn from_jwk(jwk: &Jwk) -> Option<Self> {
+        let algorithms = match &jwk.algorithm {
+            AlgorithmParameters::RSA(_) => RSA_ALGORITHMS,
+            AlgorithmParameters::EllipticCurve(parameters) => match parameters.curve {
+                EllipticCurve::P256 => &[Algorithm::ES256],
+                EllipticCurve::P384 => &[Algorithm::ES384],
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Self {
+            id: jwk.common.key_id.clone(),
+            key: DecodingKey::from_jwk(jwk).ok()?,
+            algorithms,
+        })
+    }
+}
+
+/// Parses a PEM-encoded public key.
+fn parse_pem(pem: &str) -> anyhow::Result<VerificationKey> {
+    let (key, algorithms) = if let Ok(key) = DecodingKey::from_rsa_pem(pem.as_bytes()) {
+        (key, RSA_ALGORITHMS)
+    } else {
+        let key = DecodingKey::from_ec_pem(pem.as_bytes())
+            .context("unsupported public key, expecting a RSA, P-256 or P-384 one")?;
+        // The curve of the key is checked by the signature verification.
+        (key, &[Algorithm::ES256, Algorithm::ES384][..])
+    };
+    Ok(VerificationKey {
+        id: None,
+        key,
+        algorithms,
+    })
+}
+
+/// JWKS file, whose keys are read one by one to skip the unsupported ones.
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Value>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum JwtError {
+    Malformed,
+    UnsupportedAlgorithm(String),
+    InvalidSignature,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+}
+
+impl std::fmt::Display for JwtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "malformed token"),
+            Self::UnsupportedAlgorithm(algorithm) => {
+                write!(f, "unsupported signature algorithm `{algorithm}`")
+            }
+            Self::InvalidSignature => write!(f, "invalid signature"),
+            Self::Expired => write!(f, "expired token"),
+            Self::NotYetValid => write!(f, "token not yet valid"),
+            Self::InvalidIssuer => write!(f, "unexpected issuer"),
+            Self::InvalidAudience => write!(f, "unexpected audience"),
+        }
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for JwtError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        match err.into_kind() {
+            ErrorKind::InvalidSignature => Self::InvalidSignature,
+            ErrorKind::ExpiredSignature => Self::Expired,
+            ErrorKind::ImmatureSignature => Self::NotYetValid,
+            ErrorKind::InvalidIssuer => Self::InvalidIssuer,
+            ErrorKind::InvalidAudience => Self::InvalidAudience,
+            ErrorKind::MissingRequiredClaim(claim) => match claim.as_str() {
+                "exp" => Self::Expired,
+                "iss" => Self::InvalidIssuer,
+                "aud" => Self::InvalidAudience,
+                _ => Self::Malformed,
+            },
+            _ => Self::Malformed,
+        }
+    }
+}
+
+/// Validator of JWTs, signed by one of the configured keys.
+pub(crate) struct JwtValidator {
+    keys: Vec<VerificationKey>,
+    validation: Validation,
+    roles_claim: String,
+    scopes_claim: String,
+}
+
+/// Validation of the claims of the tokens, whatever their signature algorithm.
+fn validation(config: &Config) -> Validation {
+    let mut validation = Validation::default();
+    validation.leeway = config.jwt_leeway;
+    validation.validate_nbf = true;
+    let mut required = vec!["exp"];
+    if let Some(issuer) = &config.jwt_issuer {
+        validation.set_issuer(&[issuer]);
+        required.push("iss");
+    }
+    match &config.jwt_audience {
+        Some(audience) => {
+            validation.set_audience(&[audience]);
+            required.push("aud");
+        }
+        None => validation.validate_aud = false,
+    }
+    validation.set_required_spec_claims(&required);
+    validation
+}
+
+impl JwtValidator {
+    /// Loads the keys of the configuration, returning `None` if there is none.
+    #[instrument(skip_all)]
+    pub(crate) async fn create(config: &Config) -> anyhow::Result<Option<Self>> {
+        let mut keys = Vec::new();
+        for path in &config.jwt_public_keys {
+            let pem = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("error reading public key `{}`", path.display()))?;
+            let key = parse_pem(&pem)
+                .with_context(|| format!("error parsing public key `{}`", path.display()))?;
+            keys.push(key);
+        }
+        if let Some(path) = &config.jwt_jwks_file {
+            let content = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("error reading JWKS file `{}`", path.display()))?;
+            let jwks = serde_json::from_slice::<Jwks>(&content)
+                .with_context(|| format!("error parsing JWKS file `{}`", path.display()))?;
+            for jwk in jwks.keys {
+                let key = serde_json::from_value::<Jwk>(jwk.clone())
+                    .ok()
+                    .and_then(|jwk| VerificationKey::from_jwk(&jwk));
+                match key {
+                    Some(key) => keys.push(key),
+                    None => {
+                        let field = |name| jwk.get(name).and_then(Value::as_str);
+                        let (kty, kid) = (field("kty"), field("kid"));
+                        warn!(msg = "ignoring unsupported JWK", kty, kid);
+                    }
+                }
+            }
+            if keys.is_empty() {
+                bail!("no supported key in JWKS file `{}`", path.display());
+            }
+        }
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        info!(msg = "JWT keys loaded", count = keys.len());
+        Ok(Some(Self {
+            keys,
+            validation: validation(config),
+            roles_claim: config.jwt_roles_claim.clone(),
+            scopes_claim: config.jwt_scopes_claim.clone(),
+        }))
+    }
+
+    /// Validates a token, returning its claims.
+    pub(crate) fn validate(&self, token: &str) -> Result<Claims, JwtError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| JwtError::Malformed)?;
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+        ) {
+            return Err(JwtError::UnsupportedAlgorithm(format!("{:?}", header.alg)));
+        }
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+        let candidates = self.keys.iter().filter(|key| {
+            key.algorithms.contains(&header.alg)
+                && (header.kid.is_none() || key.id.is_none() || key.id == header.kid)
+        });
+        for key in candidates {
+            match jsonwebtoken::decode::<Claims>(token, &key.key, &validation) {
+                Ok(data) => return Ok(data.claims),
+                // Another key may have signed the token.
+                Err(err) if *err.kind() == ErrorKind::InvalidSignature => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Err(JwtError::InvalidSignature)
+    }
+
+    /// Returns the roles or groups listed by the claims, as a single string or an array of them.
+    pub(crate) fn roles(&self, claims: &Claims) -> Vec<String> {
+        match claims.get(&self.roles_claim) {
+            Some(Value::String(role)) => vec![role.clone()],
+            Some(Value::Array(roles)) => strings(roles),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns the scopes granted by the claims, as a space-separated string or an array of them.
+    pub(crate) fn scopes(&self, claims: &Claims) -> Vec<String> {
+        match claims.get(&self.scopes_claim) {
+            Some(Value::String(scopes)) => scopes.split_whitespace().map(str::to_owned).collect(),
+            Some(Value::Array(scopes)) => strings(scopes),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn strings(values: &[Value]) -> Vec<String> {
+    values
+        .iter()
+        .filter_map(Value::as_str)
+        .map(str::to_owned)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::json;
+
+    use super::*;
+
+    /// `SubjectPublicKeyInfo` prefix of an uncompressed P-256 point.
+    const P256_SPKI_PREFIX: &str = "3059301306072a8648ce3d020106082a8648ce3d030107034200";
+
+    /// PKCS #8 document and public key of a P-256 key pair.
+    fn generate_key_pair() -> (Vec<u8>, Vec<u8>) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap();
+        (
+            pkcs8.as_ref().to_vec(),
+            key_pair.public_key().as_ref().to_vec(),
+        )
+    }
+
+    fn sign(pkcs8: &[u8], header: &Header, claims: &Value) -> String {
+        jsonwebtoken::encode(header, claims, &EncodingKey::from_ec_der(pkcs8)).unwrap()
+    }
+
+    fn es256(kid: Option<&str>) -> Header {
+        Header {
+            kid: kid.map(str::to_owned),
+            ..Header::new(Algorithm::ES256)
+        }
+    }
+
+    fn validator(public_key: &[u8]) -> JwtValidator {
+        let mut validation = Validation::default();
+        validation.validate_nbf = true;
+        validation.set_issuer(&["sso"]);
+        validation.set_audience(&["config-api"]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        JwtValidator {
+            keys: vec![VerificationKey {
+                id: Some("k1".into()),
+                key: DecodingKey::from_ec_der(public_key),
+                algorithms: &[Algorithm::ES256],
+            }],
+            validation,
+            roles_claim: "groups".into(),
+            scopes_claim: "scope".into(),
+        }
+    }
+
+    fn claims() -> Value {
+        json!({
+            "sub": "alice",
+            "iss": "sso",
+            "aud": ["other", "config-api"],
+            "exp": jsonwebtoken::get_current_timestamp() + 10,
+            "groups": ["maintenance"],
+            "scope": "openid read write",
+        })
+    }
+
+    #[test]
+    fn pem() {
+        let (pkcs8, public_key) = generate_key_pair();
+        let der = [hex::decode(P256_SPKI_PREFIX).unwrap(), public_key].concat();
+        let pem = format!(
+            "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n",
+            STANDARD.encode(der)
+        );
+        let key = parse_pem(&pem).unwrap();
+        assert_eq!(key.algorithms, [Algorithm::ES256, Algorithm::ES384]);
+        let validator = JwtValidator {
+            keys: vec![key],
+            ..validator(&[])
+        };
+        let token = sign(&pkcs8, &es256(None), &claims());
+        assert!(validator.validate(&token).is_ok());
+        assert!(parse_pem("-----BEGIN PUBLIC KEY-----\nMAA=\n").is_err());
+    }
+
+    #[test]
+    fn jwks() {
+        let jwks = serde_json::from_value::<Jwks>(json!({
+            "keys": [
+                { "kty": "RSA", "kid": "r1", "n": "AQAB", "e": "AQAB" },
+                { "kty": "oct", "k": "c2VjcmV0" },
+            ],
+        }))
+        .unwrap();
+        let keys = jwks
+            .keys
+            .into_iter()
+            .map(|jwk| {
+                let jwk = serde_json::from_value::<Jwk>(jwk).unwrap();
+                VerificationKey::from_jwk(&jwk).map(|key| (key.id, key.algorithms))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, [Some((Some("r1".into()), RSA_ALGORITHMS)), None]);
+    }
+
+    #[test]
+    fn valid_token() {
+        let (pkcs8, public_key) = generate_key_pair();
+        let validator = validator(&public_key);
+        let token = sign(&pkcs8, &es256(Some("k1")), &claims());
+        let claims = validator.validate(&token).unwrap();
+        assert_eq!(claims["sub"], "alice");
+        assert_eq!(validator.roles(&claims), ["maintenance"]);
+        assert_eq!(validator.scopes(&claims), ["openid", "read", "write"]);
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        let (pkcs8, public_key) = generate_key_pair();
+        let validator = validator(&public_key);
+        let validate = |claims: &Value| validator.validate(&sign(&pkcs8, &es256(None), claims));
+        let now = jsonwebtoken::get_current_timestamp();
+
+        let mut expired = claims();
+        expired["exp"] = json!(now - 61);
+        assert_eq!(validate(&expired), Err(JwtError::Expired));
+        let mut early = claims();
+        early["nbf"] = json!(now + 61);
+        assert_eq!(validate(&early), Err(JwtError::NotYetValid));
+        let mut issuer = claims();
+        issuer["iss"] = json!("other");
+        assert_eq!(validate(&issuer), Err(JwtError::InvalidIssuer));
+        let mut no_issuer = claims();
+        no_issuer.as_object_mut().unwrap().remove("iss");
+        assert_eq!(validate(&no_issuer), Err(JwtError::InvalidIssuer));
+        let mut audience = claims();
+        audience["aud"] = json!("other");
+        assert_eq!(validate(&audience), Err(JwtError::InvalidAudience));
+
+        let (other, _) = generate_key_pair();
+        assert_eq!(
+            validator.validate(&sign(&other, &es256(None), &claims())),
+            Err(JwtError::InvalidSignature)
+        );
+        let hmac = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims(),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert_eq!(
+            validator.validate(&hmac),
+            Err(JwtError::UnsupportedAlgorithm("HS256".into()))
+        );
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+        let unsigned = format!("{header}.{}.", URL_SAFE_NO_PAD.encode(claims().to_string()));
+        assert_eq!(validator.validate(&unsigned), Err(JwtError::Malformed));
+        assert_eq!(validator.validate("not.a-token"), Err(JwtError::Malformed));
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use mongodb::bson::{Bson, Document};
+
+use crate::links::{DocumentLocation, LinksError};
+
+/// A document taking part in a layered configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Layer {
+    pub(crate) location: DocumentLocation,
+    pub(crate) document: Document,
+}
+
+/// Outcome of following the `_extends` chain of a document.
+#[derive(Debug)]
+pub(crate) enum LayersResolution {
+    /// Layers from the most basic one.
+    Found(Vec<Layer>),
+    /// The last location of the path does not exist.
+    NotFound(Vec<DocumentLocation>),
+    Failed(LinksError),
+}
+
+/// Deep-merges layers, from the most basic one to the most specific one.
+///
+/// Objects are recursively merged, while any other value (including arrays) replaces the one of
+/// less specific layers. A `null` value removes the field from the result, including in the
+/// objects overriding layers introduce.
+///
+/// Returns the merged document and, for each leaf value (by JSON pointer), the location of the
+/// layer it comes from.
+pub(crate) fn merge_layers(layers: &[Layer]) -> (Document, BTreeMap<String, String>) {
+    let mut merged = Document::new();
+    let mut sources = BTreeMap::new();
+    let Some((base, overriding_layers)) = layers.split_first() else {
+        return (merged, sources);
+    };
+    // The most basic layer is taken as is, its `null` values being kept.
+    let source = base.location.to_string();
+    for (key, value) in &base.document {
+        merged.insert(key, value.clone());
+        record_sources(&mut sources, &pointer_token("", key), value, &source);
+    }
+    for layer in overriding_layers {
+        let source = layer.location.to_string();
+        merge_into(&mut merged, &layer.document, "", &source, &mut sources);
+    }
+    (merged, sources)
+}
+
+fn merge_into(
+    target: &mut Document,
+    overriding: &Document,
+    prefix: &str,
+    source: &str,
+    sources: &mut BTreeMap<String, String>,
+) {
+    for (key, value) in overriding {
+        let pointer = pointer_token(prefix, key);
+        match (target.get_mut(key), value) {
+            (Some(Bson::Document(target_nested)), Bson::Document(nested)) => {
+                merge_into(target_nested, nested, &pointer, source, sources);
+            }
+            (_, Bson::Null) => {
+                target.remove(key);
+                remove_sources(sources, &pointer);
+            }
+            (_, other) => {
+                let value = without_nulls(other);
+                remove_sources(sources, &pointer);
+                record_sources(sources, &pointer, &value, source);
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Removes the `null` values of an object, as merging it into nothing would.
+fn without_nulls(value: &Bson) -> Bson {
+    match value {
+        Bson::Document(document) => Bson::Document(
+            document
+                .iter()
+                .filter(|(_, value)| **value != Bson::Null)
+                .map(|(key, value)| (key.to_owned(), without_nulls(value)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Appends a reference token to a JSON pointer.
+pub(crate) fn pointer_token(pointer: &str, key: &str) -> String {
+    format!("{pointer}/{}", key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn remove_sources(sources: &mut BTreeMap<String, String>, pointer: &str) {
+    let children_prefix = format!("{pointer}/");
+    sources.retain(|key, _| key != pointer && !key.starts_with(&children_prefix));
+}
+
+fn record_sources(
+    sources: &mut BTreeMap<String, String>,
+    pointer: &str,
+    value: &Bson,
+    source: &str,
+) {
+    match value {
+        Bson::Document(nested) if !nested.is_empty() => {
+            for (key, value) in nested {
+                record_sources(sources, &pointer_token(pointer, key), value, source);
+            }
+        }
+        _ => {
+            sources.insert(pointer.to_owned(), source.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+
+    use super::*;
+
+    fn layer(id: &str, document: Document) -> Layer {
+        Layer {
+            location: DocumentLocation::new("coll", id),
+            document,
+        }
+    }
+
+    #[test]
+    fn single_layer() {
+        let document = doc! { "_id": "a", "b": null, "c": { "d": 1 } };
+        let (merged, sources) = merge_layers(&[layer("a", document.clone())]);
+        assert_eq!(merged, document);
+        let expected = BTreeMap::from([
+            ("/_id".to_string(), "coll/a".to_string()),
+            ("/b".to_string(), "coll/a".to_string()),
+            ("/c/d".to_string(), "coll/a".to_string()),
+        ]);
+        assert_eq!(sources, expected);
+    }
+
+    #[test]
+    fn merging() {
+        let layers = [
+            layer(
+                "base",
+                doc! {
+                    "_id": "base",
+                    "keep": 1,
+                    "nested": { "a": 1, "b": 2, "c": { "d": 3 } },
+                    "list": [1, 2, 3],
+                    "removed": "x",
+                    "replaced": { "e": 4 },
+                },
+            ),
+            layer(
+                "top",
+                doc! {
+                    "_id": "top",
+                    "_extends": { "collection": "coll", "id": "base" },
+                    "nested": { "b": 20, "c": null, "f": { "g": 5, "h": null } },
+                    "list": [4],
+                    "removed": null,
+                    "replaced": "scalar",
+                },
+            ),
+        ];
+        let (merged, sources) = merge_layers(&layers);
+        let expected = doc! {
+            "_id": "top",
+            "keep": 1,
+            "nested": { "a": 1, "b": 20, "f": { "g": 5 } },
+            "list": [4],
+            "replaced": "scalar",
+            "_extends": { "collection": "coll", "id": "base" },
+        };
+        assert_eq!(merged, expected);
+        let expected = BTreeMap::from([
+            ("/_extends/collection".to_string(), "coll/top".to_string()),
+            ("/_extends/id".to_string(), "coll/top".to_string()),
+            ("/_id".to_string(), "coll/top".to_string()),
+            ("/keep".to_string(), "coll/base".to_string()),
+            ("/list".to_string(), "coll/top".to_string()),
+            ("/nested/a".to_string(), "coll/base".to_string()),
+            ("/nested/b".to_string(), "coll/top".to_string()),
+            ("/nested/f/g".to_string(), "coll/top".to_string()),
+            ("/replaced".to_string(), "coll/top".to_string()),
+        ]);
+        assert_eq!(sources, expected);
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+
+use mongodb::bson::{Bson, Document};
+
+/// Location of a document in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DocumentLocation {
+    pub(crate) collection: String,
+    pub(crate) id: Bson,
+}
+
+impl DocumentLocation {
+    pub(crate) fn new(collection: impl Into<String>, id: impl Into<Bson>) -> Self {
+        Self {
+            collection: collection.into(),
+            id: id.into(),
+        }
+    }
+
+    /// Returns the textual representation of the document id.
+    pub(crate) fn id_string(&self) -> String {
+        match &self.id {
+            Bson::String(id) => id.to_owned(),
+            Bson::ObjectId(id) => id.to_hex(),
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for DocumentLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.collection, self.id_string())
+    }
+}
+
+/// Outcome of following the `_links` of a document.
+#[derive(Debug)]
+pub(crate) enum LinksResolution {
+    /// A document without links has been reached.
+    Found {
+        document: Document,
+        path: Vec<DocumentLocation>,
+    },
+    /// The last location of the path does not exist.
+    NotFound(Vec<DocumentLocation>),
+    Failed(LinksError),
+}
+
+#[derive(Debug)]
+pub(crate) enum LinksError {
+    /// The last location of the path has already been visited.
+    Cycle(Vec<DocumentLocation>),
+    /// Following the last location of the path would exceed the maximum depth.
+    TooDeep(Vec<DocumentLocation>),
+    /// The last location of the path is in a collection the caller can not read.
+    Forbidden(Vec<DocumentLocation>),
+}
+
+/// Returns the location a document links to, if any.
+pub(crate) fn link_target(document: &Document, collection: &str) -> Option<DocumentLocation> {
+    document
+        .get("_links")
+        .and_then(|reference| reference_target(reference, collection))
+}
+
+/// Returns the location of the document a document extends, if any.
+pub(crate) fn extends_target(document: &Document, collection: &str) -> Option<DocumentLocation> {
+    document
+        .get("_extends")
+        .and_then(|reference| reference_target(reference, collection))
+}
+
+/// Returns the location targeted by a reference to another document.
+///
+/// A reference is either an `ObjectId` of a document in the same collection, or an object with
+/// `collection` and `id` (or DBRef-style `$ref` and `$id`) fields.
+fn reference_target(reference: &Bson, collection: &str) -> Option<DocumentLocation> {
+    match reference {
+        Bson::ObjectId(id) => Some(DocumentLocation::new(collection, *id)),
+        Bson::Document(target) => {
+            let collection = target
+                .get_str("collection")
+                .or_else(|_| target.get_str("$ref"))
+                .ok()?;
+            let id = target.get("id").or_else(|| target.get("$id"))?;
+            Some(DocumentLocation::new(collection, id.clone()))
+        }
+        _ => None,
+    }
+}
+
+/// Formats a resolution path, to be used as a header value.
+pub(crate) fn format_path(path: &[DocumentLocation]) -> String {
+    path.iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::doc;
+    use mongodb::bson::oid::ObjectId;
+
+    use super::*;
+
+    mod link_target {
+        use super::*;
+
+        #[test]
+        fn no_links() {
+            let document = doc! { "_id": "a", "value": 1 };
+            assert!(link_target(&document, "coll").is_none());
+        }
+
+        #[test]
+        fn unsupported_links() {
+            let document = doc! { "_id": "a", "_links": "b" };
+            assert!(link_target(&document, "coll").is_none());
+            let document = doc! { "_id": "a", "_links": { "collection": "other" } };
+            assert!(link_target(&document, "coll").is_none());
+        }
+
+        #[test]
+        fn object_id() {
+            let id = ObjectId::new();
+            let document = doc! { "_id": "a", "_links": id };
+            let expected = DocumentLocation::new("coll", id);
+            assert_eq!(link_target(&document, "coll").unwrap(), expected);
+        }
+
+        #[test]
+        fn collection_and_id() {
+            let document = doc! { "_id": "a", "_links": { "collection": "other", "id": "b" } };
+            let expected = DocumentLocation::new("other", "b");
+            assert_eq!(link_target(&document, "coll").unwrap(), expected);
+        }
+
+        #[test]
+        fn dbref() {
+            let document = doc! { "_id": "a", "_links": { "$ref": "other", "$id": 5 } };
+            let expected = DocumentLocation::new("other", 5);
+            assert_eq!(link_target(&document, "coll").unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn extends_target() {
+        let document = doc! { "_id": "a", "_links": { "collection": "other", "id": "b" } };
+        assert!(super::extends_target(&document, "coll").is_none());
+        let document = doc! { "_id": "a", "_extends": { "collection": "other", "id": "b" } };
+        let expected = DocumentLocation::new("other", "b");
+        assert_eq!(super::extends_target(&document, "coll").unwrap(), expected);
+    }
+
+    #[test]
+    fn format_path() {
+        let id = ObjectId::parse_str("0123456789abcdef01234567").unwrap();
+        let path = [
+            DocumentLocation::new("coll", "a"),
+            DocumentLocation::new("coll", id),
+        ];
+        assert_eq!(
+            super::format_path(&path),
+            "coll/a, coll/0123456789abcdef01234567"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::{Event, Subscriber, error, info, span};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{Registry, reload};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LogFormat {
+    /// Human-readable, one line per event
+    Full,
+    /// Human-readable, shorter lines
+    Compact,
+    /// Human-readable, several lines per event
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Args)]
+#[group(id = "logging")]
+pub(crate) struct Config {
+    /// Format of the log lines
+    #[arg(env, long, value_enum, default_value_t = LogFormat::Full)]
+    log_format: LogFormat,
+}
+
+/// Filter of the logs, changed at runtime either for a time, or durably by a configuration reload.
+#[derive(Clone)]
+pub(crate) struct LogFilter {
+    handle: reload::Handle<Targets, Registry>,
+    state: Arc<Mutex<FilterState>>,
+}
+
+struct FilterState {
+    /// Maximum level given by the configuration.
+    max_level: LevelFilter,
+    /// Filter used instead for a time, if any.
+    temporary: Option<TemporaryFilter>,
+}
+
+struct TemporaryFilter {
+    filter: Targets,
+    revert_at: Instant,
+    revert_task: JoinHandle<()>,
+}
+
+/// Filter in use, as returned by the admin API.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct FilterStatus {
+    /// Comma-separated directives, e.g. `config_api::db=debug,info`.
+    filter: String,
+    /// Seconds before the filter given by the configuration is used again, if not in use.
+    revert_in: Option<u64>,
+}
+
+impl LogFilter {
+    fn new(handle: reload::Handle<Targets, Registry>, max_level: LevelFilter) -> Self {
+        Self {
+            handle,
+            state: Arc::new(Mutex::new(FilterState {
+                max_level,
+                temporary: None,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FilterState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn status(&self) -> FilterStatus {
+        let state = self.lock();
+        match &state.temporary {
+            Some(temporary) => FilterStatus {
+                filter: temporary.filter.to_string(),
+                revert_in: Some(
+                    temporary
+                        .revert_at
+                        .saturating_duration_since(Instant::now())
+                        .as_secs(),
+                ),
+            },
+            None => FilterStatus {
+                filter: state.max_level.to_string(),
+                revert_in: None,
+            },
+        }
+    }
+
+    /// Sets the maximum level given by the configuration, used unless a temporary filter is.
+    pub(crate) fn set_max_level(&self, max_level: impl Into<LevelFilter>) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        state.max_level = max_level.into();
+        if state.temporary.is_none() {
+            self.reload_max_level(&state)?;
+        }
+        Ok(())
+    }
+
+    /// Uses a filter for a time, with the maximum level given by the configuration for the targets
+    /// it does not match if it has no default level.
+    pub(crate) fn set_temporary(
+        &self,
+        mut filter: Targets,
+        duration: Duration,
+    ) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        if filter.default_level().is_none() {
+            filter = filter.with_default(state.max_level);
+        }
+        self.handle.reload(filter.clone())?;
+        info!(
+            msg = "log filter changed",
+            %filter,
+            revert_in = duration.as_secs()
+        );
+        let revert_at = Instant::now() + duration;
+        let this = self.clone();
+        let revert_task = tokio::spawn(async move {
+            sleep_until(revert_at).await;
+            if let Err(err) = this.revert_expired() {
+                error!(kind = "log filter revert", %err);
+            }
+        });
+        let previous = state.temporary.replace(TemporaryFilter {
+            filter,
+            revert_at,
+            revert_task,
+        });
+        if let Some(previous) = previous {
+            previous.revert_task.abort();
+        }
+        Ok(())
+    }
+
+    /// Uses the maximum level given by the configuration again.
+    pub(crate) fn revert(&self) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        let Some(temporary) = state.temporary.take() else {
+            return Ok(());
+        };
+        temporary.revert_task.abort();
+        self.reload_max_level(&state)?;
+        info!(msg = "log filter reverted", max_level = %state.max_level);
+        Ok(())
+    }
+
+    /// Reverts the temporary filter if its time is over, as it may have been replaced since its
+    /// revert task woke up.
+    fn revert_expired(&self) -> anyhow::Result<()> {
+        let mut state = self.lock();
+        if state
+            .temporary
+            .as_ref()
+            .is_none_or(|temporary| temporary.revert_at > Instant::now())
+        {
+            return Ok(());
+        }
+        state.temporary = None;
+        self.reload_max_level(&state)?;
+        info!(msg = "log filter reverted", max_level = %state.max_level);
+        Ok(())
+    }
+
+    fn reload_max_level(&self, state: &FilterState) -> anyhow::Result<()> {
+        self.handle
+            .reload(Targets::new().with_default(state.max_level))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Default for LogFilter {
+    /// Filter of no subscriber, which cannot be changed.
+    fn default() -> Self {
+        let (_, handle) = reload::Layer::new(Targets::new());
+        Self::new(handle, LevelFilter::INFO)
+    }
+}
+
+/// Initializes the global subscriber, writing logs to the standard output.
+pub(crate) fn init(config: &Config, max_level: impl Into<LevelFilter>) -> LogFilter {
+    let max_level = max_level.into();
+    let (filter, handle) = reload::Layer::new(Targets::new().with_default(max_level));
+    let registry = tracing_subscriber::registry().with(filter);
+    let layer = tracing_subscriber::fmt::layer();
+    match config.log_format {
+        LogFormat::Full => registry.with(layer).init(),
+        LogFormat::Compact => registry.with(layer.compact()).init(),
+        LogFormat::Pretty => registry.with(layer.pretty()).init(),
+        LogFormat::Json => registry
+            .with(layer.fmt_fields(JsonFields).event_format(JsonFormat))
+            .init(),
+    }
+    LogFilter::new(handle, max_level)
+}
+
+/// Collects fields as JSON values.
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0
+            .insert(field.name().to_owned(), value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+}
+
+/// Formats the fields of spans as a JSON object.
+struct JsonFields;
+
+impl<'writer> FormatFields<'writer> for JsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut map = Map::new();
+        fields.record(&mut JsonVisitor(&mut map));
+        write!(writer, "{}", Value::Object(map))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'writer mut FormattedFields<Self>,
+        fields: &span::Record<'_>,
+    ) -> fmt::Result {
+        let mut map = serde_json::from_str(&current.fields).unwrap_or_default();
+        fields.record(&mut JsonVisitor(&mut map));
+        current.fields = Value::Object(map).to_string();
+        Ok(())
+    }
+}
+
+/// Formats events as JSON objects, with their fields and the ones of their spans.
+struct JsonFormat;
+
+impl<S> FormatEvent<S, JsonFields> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, JsonFields>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let metadata = event.metadata();
+        let mut fields = Map::new();
+        event.record(&mut JsonVisitor(&mut fields));
+        let spans = ctx
+            .event_scope()
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let mut object = Map::new();
+                object.insert("name".into(), span.name().into());
+                if let Some(formatted) = span.extensions().get::<FormattedFields<JsonFields>>()
+                    && let Ok(Value::Object(fields)) = serde_json::from_str(&formatted.fields)
+                {
+                    object.extend(fields);
+                }
+                Value::Object(object)
+            })
+            .collect::<Vec<_>>();
+        let mut line = Map::new();
+        line.insert("timestamp".into(), timestamp.into());
+        line.insert("level".into(), metadata.level().as_str().into());
+        line.insert("target".into(), metadata.target().into());
+        line.insert("fields".into(), Value::Object(fields));
+        line.insert("spans".into(), spans.into());
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use tracing::{debug, info, info_span};
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+
+    /// Log output written in memory.
+    #[derive(Clone, Default)]
+    pub(crate) struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl io::Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Buffer {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    /// Returns a subscriber writing to a buffer, with its filter.
+    pub(crate) fn filtered_subscriber(buffer: Buffer) -> (impl Subscriber, LogFilter) {
+        let (filter, handle) = reload::Layer::new(Targets::new().with_default(LevelFilter::INFO));
+        let subscriber = tracing_subscriber::registry().with(filter).with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(buffer),
+        );
+        (subscriber, LogFilter::new(handle, LevelFilter::INFO))
+    }
+
+    #[tokio::test]
+    async fn temporary() {
+        let buffer = Buffer::default();
+        let (subscriber, log_filter) = filtered_subscriber(buffer.clone());
+        let _default = tracing::subscriber::set_default(subscriber);
+        assert_eq!(
+            serde_json::to_value(log_filter.status()).unwrap(),
+            serde_json::json!({ "filter": "info", "revertIn": null })
+        );
+        let filter = "config_api::logging=debug".parse().unwrap();
+        log_filter
+            .set_temporary(filter, Duration::from_secs(60))
+            .unwrap();
+        debug!(msg = "first");
+        let status = log_filter.status();
+        assert_eq!(status.filter, "config_api::logging=debug,info");
+        assert!(status.revert_in.is_some_and(|seconds| seconds > 50));
+        log_filter.revert().unwrap();
+        debug!(msg = "second");
+        let filter = "trace".parse().unwrap();
+        log_filter.set_temporary(filter, Duration::ZERO).unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        debug!(msg = "third");
+        assert_eq!(log_filter.status().filter, "info");
+        let output = buffer.contents();
+        assert!(output.contains("first"), "{output}");
+        assert!(!output.contains("second"), "{output}");
+        assert!(!output.contains("third"), "{output}");
+    }
+
+    #[test]
+    fn json() {
+        let buffer = Buffer::default();
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(JsonFields)
+            .event_format(JsonFormat)
+            .with_writer(buffer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "request",
+                request_id = "abc",
+                status = tracing::field::Empty
+            );
+            let _entered = span.enter();
+            span.record("status", 200);
+            info!(msg = "handled", count = 2, ok = true);
+        });
+        let output = buffer.contents();
+        let line = serde_json::from_str::<Value>(&output).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "config_api::logging::tests");
+        assert_eq!(
+            line["fields"],
+            serde_json::json!({ "msg": "handled", "count": 2, "ok": true })
+        );
+        assert_eq!(
+            line["spans"],
+            serde_json::json!([{ "name": "request", "request_id": "abc", "status": 200 }])
+        );
+        assert!(
+            line["timestamp"]
+                .as_str()
+                .is_some_and(|timestamp| timestamp.ends_with('Z'))
+        );
+    }
+}
@@ -1,19 +1,47 @@
+use std::env;
+use std::net::SocketAddr;
+
 use anyhow::Context;
 use clap::Parser;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 use futures_util::StreamExt;
-use signal_hook::consts::TERM_SIGNALS;
+use signal_hook::consts::{SIGHUP, TERM_SIGNALS};
 use signal_hook::low_level::signal_name;
 use signal_hook_tokio::Signals;
 use tokio::net::TcpListener;
 use tracing::{Instrument, error, info, info_span, instrument};
 
+use access_log::AccessLog;
+use auth::Authentication;
+use authorization::CollectionFilter;
 use config_api::CommonArgs;
-use db::Database;
+use db::{Database, FindApiKeyChannel};
+use exposure::Exposure;
+use logging::LogFilter;
+use reload::{Reloadable, Reloads};
+use tls::{ServerCertificate, Tls, TlsClient};
 
+mod access_log;
+mod audit;
+mod auth;
+mod authorization;
 mod channel;
+mod config_file;
 mod db;
+mod diff;
+mod exposure;
 mod http_api;
+mod interpolation;
+mod jwt;
+mod layers;
+mod links;
+mod logging;
+mod patch;
+mod problem;
+mod reload;
+mod schema;
+mod tls;
+mod trace_context;
 
 #[derive(Parser)]
 struct Args {
@@ -23,6 +51,27 @@ struct Args {
     #[command(flatten)]
     mongodb: db::Config,
 
+    #[command(flatten)]
+    exposure: exposure::Config,
+
+    #[command(flatten)]
+    auth: auth::Config,
+
+    #[command(flatten)]
+    jwt: jwt::Config,
+
+    #[command(flatten)]
+    authorization: authorization::Config,
+
+    #[command(flatten)]
+    tls: tls::Config,
+
+    #[command(flatten)]
+    logging: logging::Config,
+
+    #[command(flatten)]
+    access_log: access_log::Config,
+
     #[command(flatten)]
     verbosity: Verbosity<InfoLevel>,
 }
@@ -36,28 +85,137 @@ async fn handle_signals(signals: Signals) {
     }
 }
 
+/// Settings replaced when the configuration is reloaded, the others being only read at startup.
+struct Reloader {
+    log_filter: LogFilter,
+    authentication: Reloadable<Authentication>,
+    collection_filter: Reloadable<CollectionFilter>,
+    internal_collections: Vec<String>,
+    certificate: Option<ServerCertificate>,
+    find_api_key_channel: FindApiKeyChannel,
+}
+
+impl Reloader {
+    /// Reads the configuration again, replacing the settings only if all of them are valid.
+    async fn reload(&self) -> anyhow::Result<()> {
+        let (args, _) = config_file::try_parse::<Args>(env::args_os())?;
+        let authentication =
+            Authentication::create(&args.auth, &args.jwt, self.find_api_key_channel.clone())
+                .await?;
+        let certified_key = self
+            .certificate
+            .as_ref()
+            .map(|certificate| certificate.load(&args.tls))
+            .transpose()?;
+        self.log_filter.set_max_level(args.verbosity)?;
+        self.authentication.set(authentication);
+        self.collection_filter
+            .set(collection_filter(&args, &self.internal_collections));
+        if let (Some(certificate), Some(certified_key)) = (&self.certificate, certified_key) {
+            certificate.set(certified_key);
+        }
+        Ok(())
+    }
+}
+
+/// Filter of the accessible collections, refusing the ones used by the service itself.
+fn collection_filter(args: &Args, internal_collections: &[String]) -> CollectionFilter {
+    let internal = internal_collections
+        .iter()
+        .map(String::as_str)
+        .chain(args.auth.api_keys_collection())
+        .map(str::to_owned)
+        .collect();
+    CollectionFilter::from(&args.authorization).with_internal(internal)
+}
+
+#[instrument(skip_all)]
+async fn handle_reload_signals(signals: Signals, reloader: Reloader, reloads: Reloads) {
+    let mut signals_stream = signals.map(|signal| signal_name(signal).unwrap_or("unknown"));
+    info!(status = "started");
+    while let Some(signal) = signals_stream.next().await {
+        info!(
+            msg = "received signal",
+            reaction = "reloading configuration",
+            signal
+        );
+        match reloader.reload().await {
+            Ok(()) => {
+                reloads.record(true);
+                info!(msg = "configuration reloaded");
+            }
+            Err(err) => {
+                reloads.record(false);
+                error!(kind = "configuration reload", err = format!("{err:#}"));
+            }
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let args = config_file::parse::<Args>()?;
 
-    tracing_subscriber::fmt()
-        .with_max_level(args.verbosity)
-        .init();
+    let log_filter = logging::init(&args.logging, args.verbosity);
 
     let database = Database::create(&args.mongodb).await?;
     let (health_channel, health_task) = database.clone().handle_health();
     let (get_collection_channel, get_collection_task) = database.handle_get_collection();
     let (get_document_channel, get_document_task) = database.handle_get_document();
     let (patch_config_channel, patch_config_task) = database.handle_patch_config();
+    let (delete_document_channel, delete_document_task) = database.handle_delete_document();
+    let (get_schema_channel, get_schema_task) = database.handle_get_schema();
+    let (put_schema_channel, put_schema_task) = database.handle_put_schema();
+    let (validate_collection_channel, validate_collection_task) =
+        database.handle_validate_collection();
+    let (get_history_channel, get_history_task) = database.handle_get_history();
+    let (get_revision_channel, get_revision_task) = database.handle_get_revision();
+    let (find_api_key_channel, find_api_key_task) = database.handle_find_api_key();
+    let authentication = Reloadable::from(
+        Authentication::create(&args.auth, &args.jwt, find_api_key_channel.clone()).await?,
+    );
+    let internal_collections = database.internal_collections();
+    let collection_filter = Reloadable::from(collection_filter(&args, &internal_collections));
+
+    let tls = Tls::create(&args.tls).await?;
 
     let signals = Signals::new(TERM_SIGNALS).context("error registering termination signals")?;
     let signals_handle = signals.handle();
+    let reload_signals =
+        Signals::new([SIGHUP]).context("error registering configuration reload signal")?;
+    let reload_signals_handle = reload_signals.handle();
+    let reloads = Reloads::default();
+    let reloader = Reloader {
+        log_filter: log_filter.clone(),
+        authentication: authentication.clone(),
+        collection_filter: collection_filter.clone(),
+        internal_collections,
+        certificate: tls.as_ref().map(|tls| tls.certificate.clone()),
+        find_api_key_channel,
+    };
+    let reload_task = tokio::spawn(handle_reload_signals(
+        reload_signals,
+        reloader,
+        reloads.clone(),
+    ));
 
     let app = http_api::app(http_api::AppState {
         health_channel,
         get_collection_channel,
         get_document_channel,
         patch_config_channel,
+        delete_document_channel,
+        get_schema_channel,
+        put_schema_channel,
+        validate_collection_channel,
+        get_history_channel,
+        get_revision_channel,
+        authentication,
+        collection_filter,
+        exposure: Exposure::from(&args.exposure),
+        access_log: AccessLog::from(&args.access_log),
+        reloads,
+        log_filter,
     });
     async move {
         let listener = match TcpListener::bind(&args.common.listen_address).await {
@@ -70,10 +228,27 @@ async fn main() -> anyhow::Result<()> {
                 return;
             }
         };
-        if let Err(err) = axum::serve(listener, app.into_make_service())
-            .with_graceful_shutdown(handle_signals(signals))
-            .await
-        {
+        let served = match tls {
+            Some(tls) => {
+                let served = axum::serve(
+                    tls.listener(listener),
+                    app.into_make_service_with_connect_info::<TlsClient>(),
+                )
+                .with_graceful_shutdown(handle_signals(signals))
+                .await;
+                tls.reload_task.abort();
+                served
+            }
+            None => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(handle_signals(signals))
+                .await
+            }
+        };
+        if let Err(err) = served {
             error!(kind = "HTTP server", %err);
         }
         info!(status = "terminating");
@@ -82,12 +257,21 @@ async fn main() -> anyhow::Result<()> {
     .await;
 
     signals_handle.close();
+    reload_signals_handle.close();
 
     tokio::try_join!(
         health_task,
         get_collection_task,
         get_document_task,
-        patch_config_task
+        patch_config_task,
+        delete_document_task,
+        get_schema_task,
+        put_schema_task,
+        validate_collection_task,
+        get_history_task,
+        get_revision_task,
+        find_api_key_task,
+        reload_task
     )
     .context("error joining task(s)")?;
 
@@ -0,0 +1,1053 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use mongodb::bson::{Bson, Document, doc};
+use serde::Deserialize;
+
+const PATCH_AUTHORIZATION: &str = "patchAllowedFields";
+const UNSET_AUTHORIZATION: &str = "unsetAllowedFields";
+
+/// JSON Pointer (RFC 6901), stored as unescaped reference tokens.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct JsonPointer(Vec<String>);
+
+impl TryFrom<String> for JsonPointer {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.is_empty() {
+            return Ok(Self(Vec::new()));
+        }
+        let Some(tokens) = value.strip_prefix('/') else {
+            return Err(format!("JSON pointer `{value}` must start with `/`"));
+        };
+        let tokens = tokens
+            .split('/')
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect();
+        Ok(Self(tokens))
+    }
+}
+
+impl fmt::Display for JsonPointer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in &self.0 {
+            write!(f, "/{}", token.replace('~', "~0").replace('/', "~1"))?;
+        }
+        Ok(())
+    }
+}
+
+impl JsonPointer {
+    pub(crate) fn new(tokens: Vec<String>) -> Self {
+        Self(tokens)
+    }
+
+    /// Returns the value this pointer refers to in a document, if any.
+    pub(crate) fn get<'a>(&self, root: &'a Bson) -> Option<&'a Bson> {
+        pointer_get(root, self)
+    }
+
+    /// Returns the name of the top-level field this pointer refers into.
+    ///
+    /// The root pointer refers to the whole document, and returns an empty name.
+    pub(crate) fn top_level(&self) -> &str {
+        self.0.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Returns the MongoDB dot notation equivalent, if any.
+    fn dotted(&self) -> Option<String> {
+        if self.0.is_empty()
+            || self
+                .0
+                .iter()
+                .any(|token| token.is_empty() || token.contains('.') || token.starts_with('$'))
+        {
+            return None;
+        }
+        Some(self.0.join("."))
+    }
+
+    fn has_index_token(&self) -> bool {
+        self.0.iter().any(|token| parse_index(token).is_some())
+    }
+
+    fn last_is_index(&self) -> bool {
+        self.0
+            .last()
+            .is_some_and(|token| parse_index(token).is_some())
+    }
+
+    fn parent(&self) -> Option<(Self, &str)> {
+        let (last, parent) = self.0.split_last()?;
+        Some((Self(parent.to_vec()), last))
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.0.iter().zip(&other.0).all(|(a, b)| a == b)
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub(crate) enum PatchOperation {
+    Add {
+        path: JsonPointer,
+        value: Bson,
+    },
+    Remove {
+        path: JsonPointer,
+    },
+    Replace {
+        path: JsonPointer,
+        value: Bson,
+    },
+    Move {
+        from: JsonPointer,
+        path: JsonPointer,
+    },
+    Copy {
+        from: JsonPointer,
+        path: JsonPointer,
+    },
+    Test {
+        path: JsonPointer,
+        value: Bson,
+    },
+}
+
+/// MongoDB update operators usable in a patch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum UpdateOperator {
+    Inc,
+    Min,
+    Max,
+    Push,
+    AddToSet,
+    Pull,
+}
+
+impl TryFrom<&str> for UpdateOperator {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "$inc" => Ok(Self::Inc),
+            "$min" => Ok(Self::Min),
+            "$max" => Ok(Self::Max),
+            "$push" => Ok(Self::Push),
+            "$addToSet" => Ok(Self::AddToSet),
+            "$pull" => Ok(Self::Pull),
+            other => Err(format!("unsupported update operator `{other}`")),
+        }
+    }
+}
+
+impl UpdateOperator {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inc => "$inc",
+            Self::Min => "$min",
+            Self::Max => "$max",
+            Self::Push => "$push",
+            Self::AddToSet => "$addToSet",
+            Self::Pull => "$pull",
+        }
+    }
+
+    /// Returns the name of the `_authorization` document field granting this operator.
+    fn authorization(self) -> &'static str {
+        match self {
+            Self::Inc => "incAllowedFields",
+            Self::Min => "minAllowedFields",
+            Self::Max => "maxAllowedFields",
+            Self::Push => "pushAllowedFields",
+            Self::AddToSet => "addToSetAllowedFields",
+            Self::Pull => "pullAllowedFields",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum Patch {
+    /// Replacement of top-level fields.
+    Fields(HashMap<String, Bson>),
+    /// JSON Merge Patch (RFC 7396).
+    Merge(Document),
+    /// JSON Patch (RFC 6902).
+    Json(Vec<PatchOperation>),
+    /// MongoDB update operators, atomically applied.
+    Operators(BTreeMap<UpdateOperator, Document>),
+}
+
+/// Top-level fields touched by a patch, by name of the `_authorization` document field granting
+/// the change.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct TouchedFields(BTreeMap<&'static str, BTreeSet<String>>);
+
+impl TouchedFields {
+    fn add(&mut self, authorization: &'static str, field: &str) {
+        self.0
+            .entry(authorization)
+            .or_default()
+            .insert(field.to_owned());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&'static str, Vec<&str>)> {
+        self.0.iter().map(|(authorization, fields)| {
+            (*authorization, fields.iter().map(String::as_str).collect())
+        })
+    }
+
+    /// Lists all the touched fields.
+    pub(crate) fn fields(&self) -> BTreeSet<&str> {
+        self.0.values().flatten().map(String::as_str).collect()
+    }
+
+    /// Lists the touched fields an `_authorization` document does not grant, either to everyone
+    /// through its top-level fields or to one of the given roles through its `roles` field.
+    pub(crate) fn denied_by(&self, authorization: &Document, roles: &[String]) -> BTreeSet<String> {
+        let grants = std::iter::once(authorization).chain(roles.iter().filter_map(|role| {
+            authorization
+                .get_document("roles")
+                .ok()?
+                .get_document(role)
+                .ok()
+        }));
+        let grants = grants.collect::<Vec<_>>();
+        let mut denied = BTreeSet::new();
+        for (kind, fields) in self.iter() {
+            for field in fields {
+                let granted = grants.iter().any(|grant| {
+                    grant.get_array(kind).is_ok_and(|allowed| {
+                        allowed
+                            .iter()
+                            .any(|allowed| allowed.as_str() == Some(field))
+                    })
+                });
+                if !granted {
+                    denied.insert(field.to_owned());
+                }
+            }
+        }
+        denied
+    }
+}
+
+/// A patch translated to a single MongoDB update.
+#[derive(Debug, PartialEq)]
+pub(crate) struct AtomicUpdate {
+    /// Conditions to add to the update filter.
+    pub(crate) preconditions: Document,
+    /// MongoDB update document.
+    pub(crate) update: Document,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum PatchError {
+    /// A location referenced by the patch does not exist.
+    NotFound(String),
+    /// A `test` operation failed.
+    TestFailed(String),
+    /// The patch can not be applied to a document.
+    Invalid(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "path `{path}` not found"),
+            Self::TestFailed(path) => write!(f, "test failed at path `{path}`"),
+            Self::Invalid(reason) => write!(f, "invalid patch: {reason}"),
+        }
+    }
+}
+
+impl Patch {
+    /// Builds a patch from a JSON object, either replacing top-level fields or containing only
+    /// update operators.
+    pub(crate) fn from_object(object: HashMap<String, Bson>) -> Result<Self, String> {
+        if !object.keys().any(|key| key.starts_with('$')) {
+            return Ok(Self::Fields(object));
+        }
+        let operators = object
+            .into_iter()
+            .map(|(key, value)| {
+                let operator = UpdateOperator::try_from(key.as_str()).map_err(|err| {
+                    format!("{err} (update operators and fields can not be mixed)")
+                })?;
+                match value {
+                    Bson::Document(changes) => Ok((operator, changes)),
+                    _ => Err(format!("`{key}` value must be an object")),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self::Operators(operators))
+    }
+
+    pub(crate) fn touched_fields(&self) -> TouchedFields {
+        let mut touched = TouchedFields::default();
+        match self {
+            Self::Fields(changes) => {
+                for key in changes.keys() {
+                    touched.add(PATCH_AUTHORIZATION, key);
+                }
+            }
+            Self::Merge(changes) => {
+                for (key, value) in changes {
+                    if *value == Bson::Null {
+                        touched.add(UNSET_AUTHORIZATION, key);
+                    } else {
+                        touched.add(PATCH_AUTHORIZATION, key);
+                    }
+                }
+            }
+            Self::Json(operations) => {
+                for operation in operations {
+                    match operation {
+                        PatchOperation::Add { path, .. }
+                        | PatchOperation::Replace { path, .. }
+                        | PatchOperation::Copy { path, .. } => {
+                            touched.add(PATCH_AUTHORIZATION, path.top_level());
+                        }
+                        PatchOperation::Remove { path } => {
+                            touched.add(UNSET_AUTHORIZATION, path.top_level());
+                        }
+                        PatchOperation::Move { from, path } => {
+                            touched.add(UNSET_AUTHORIZATION, from.top_level());
+                            touched.add(PATCH_AUTHORIZATION, path.top_level());
+                        }
+                        PatchOperation::Test { .. } => {}
+                    }
+                }
+            }
+            Self::Operators(operators) => {
+                for (operator, changes) in operators {
+                    for key in changes.keys() {
+                        let top_level = key.split('.').next().unwrap_or_default();
+                        touched.add(operator.authorization(), top_level);
+                    }
+                }
+            }
+        }
+        touched
+    }
+
+    /// Returns the paths whose values the patch reads, which its outcome reveals.
+    pub(crate) fn read_paths(&self) -> Vec<&JsonPointer> {
+        let Self::Json(operations) = self else {
+            return Vec::new();
+        };
+        operations
+            .iter()
+            .filter_map(|operation| match operation {
+                PatchOperation::Copy { from, .. } | PatchOperation::Move { from, .. } => Some(from),
+                PatchOperation::Test { path, .. } => Some(path),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Translates the patch into a single MongoDB update, if possible.
+    pub(crate) fn atomic_update(&self) -> Option<AtomicUpdate> {
+        let mut set = Document::new();
+        let mut unset = Document::new();
+        let mut push = Document::new();
+        let mut preconditions = Vec::new();
+
+        match self {
+            Self::Fields(changes) => {
+                set.extend(changes.iter().map(|(k, v)| (k.to_owned(), v.to_owned())));
+            }
+            Self::Merge(changes) => {
+                merge_to_update(changes, &mut set, &mut unset)?;
+            }
+            Self::Operators(operators) => {
+                let update = operators
+                    .iter()
+                    .filter(|(_, changes)| !changes.is_empty())
+                    .map(|(operator, changes)| (operator.as_str().to_owned(), changes.into()))
+                    .collect::<Document>();
+                return (!update.is_empty()).then_some(AtomicUpdate {
+                    preconditions: Document::new(),
+                    update,
+                });
+            }
+            Self::Json(operations) => {
+                let mut modified: Vec<&JsonPointer> = Vec::new();
+                for operation in operations {
+                    let path = match operation {
+                        PatchOperation::Add { path, value } => {
+                            let (parent, last) = path.parent()?;
+                            let parent_type = if last == "-" {
+                                push.insert(parent.dotted()?, value.clone());
+                                "array"
+                            } else if path.last_is_index() {
+                                return None;
+                            } else {
+                                set.insert(path.dotted()?, value.clone());
+                                "object"
+                            };
+                            if let Some(dotted_parent) = parent.dotted() {
+                                preconditions
+                                    .push(doc! { dotted_parent: { "$type": parent_type } });
+                            }
+                            path
+                        }
+                        PatchOperation::Replace { path, value } => {
+                            let dotted = path.dotted()?;
+                            preconditions.push(doc! { &dotted: { "$exists": true } });
+                            set.insert(dotted, value.clone());
+                            path
+                        }
+                        PatchOperation::Remove { path } => {
+                            if path.last_is_index() {
+                                return None;
+                            }
+                            let dotted = path.dotted()?;
+                            preconditions.push(doc! { &dotted: { "$exists": true } });
+                            unset.insert(dotted, "");
+                            path
+                        }
+                        PatchOperation::Test { path, value } => {
+                            if path.has_index_token()
+                                || matches!(value, Bson::Document(_) | Bson::Array(_))
+                                || modified.iter().any(|m| m.overlaps(path))
+                            {
+                                return None;
+                            }
+                            let field_path = format!("${}", path.dotted()?);
+                            preconditions.push(doc! {
+                                "$expr": { "$eq": [field_path, { "$literal": value.clone() }] },
+                            });
+                            continue;
+                        }
+                        PatchOperation::Move { .. } | PatchOperation::Copy { .. } => return None,
+                    };
+                    if modified.iter().any(|m| m.overlaps(path)) {
+                        return None;
+                    }
+                    modified.push(path);
+                }
+            }
+        }
+
+        let mut update = Document::new();
+        if !set.is_empty() {
+            update.insert("$set", set);
+        }
+        if !unset.is_empty() {
+            update.insert("$unset", unset);
+        }
+        if !push.is_empty() {
+            update.insert("$push", push);
+        }
+        if update.is_empty() {
+            return None;
+        }
+        let preconditions = if preconditions.is_empty() {
+            Document::new()
+        } else {
+            doc! { "$and": preconditions }
+        };
+        Some(AtomicUpdate {
+            preconditions,
+            update,
+        })
+    }
+
+    /// Applies the patch to a document.
+    pub(crate) fn apply(&self, document: &mut Document) -> Result<(), PatchError> {
+        let mut root = Bson::Document(std::mem::take(document));
+        let result = match self {
+            Self::Fields(changes) => {
+                if let Bson::Document(target) = &mut root {
+                    target.extend(changes.iter().map(|(k, v)| (k.to_owned(), v.to_owned())));
+                }
+                Ok(())
+            }
+            Self::Merge(changes) => {
+                merge(&mut root, changes);
+                Ok(())
+            }
+            Self::Json(operations) => operations
+                .iter()
+                .try_for_each(|operation| apply_operation(&mut root, operation)),
+            // Update operators are only applied by the database, through an atomic update.
+            Self::Operators(_) => Err(PatchError::Invalid(
+                "update operators must not be empty".into(),
+            )),
+        };
+        match root {
+            Bson::Document(patched) => *document = patched,
+            _ => {
+                return Err(PatchError::Invalid(
+                    "document root must be an object".into(),
+                ));
+            }
+        }
+        result
+    }
+}
+
+/// Translates a merge patch into `$set` and `$unset` changes of top-level fields.
+fn merge_to_update(changes: &Document, set: &mut Document, unset: &mut Document) -> Option<()> {
+    for (key, value) in changes {
+        if key.is_empty() || key.contains('.') || key.starts_with('$') {
+            return None;
+        }
+        match value {
+            Bson::Null => {
+                unset.insert(key, "");
+            }
+            // Merged into the current value, which it replaces unless it is an object: the
+            // result depends on a type an update does not know.
+            Bson::Document(_) => return None,
+            other => {
+                set.insert(key, other.clone());
+            }
+        }
+    }
+    Some(())
+}
+
+fn merge(target: &mut Bson, changes: &Document) {
+    if !matches!(target, Bson::Document(_)) {
+        *target = Bson::Document(Document::new());
+    }
+    let Bson::Document(target_document) = target else {
+        unreachable!()
+    };
+    for (key, value) in changes {
+        match value {
+            Bson::Null => {
+                target_document.remove(key);
+            }
+            Bson::Document(nested) => {
+                let mut nested_target = target_document.remove(key).unwrap_or(Bson::Null);
+                merge(&mut nested_target, nested);
+                target_document.insert(key, nested_target);
+            }
+            other => {
+                target_document.insert(key, other.clone());
+            }
+        }
+    }
+}
+
+fn parse_index(token: &str) -> Option<usize> {
+    if token.is_empty()
+        || !token.bytes().all(|b| b.is_ascii_digit())
+        || (token.len() > 1 && token.starts_with('0'))
+    {
+        return None;
+    }
+    token.parse().ok()
+}
+
+fn pointer_get<'a>(root: &'a Bson, pointer: &JsonPointer) -> Option<&'a Bson> {
+    pointer
+        .0
+        .iter()
+        .try_fold(root, |current, token| match current {
+            Bson::Document(document) => document.get(token),
+            Bson::Array(array) => array.get(parse_index(token)?),
+            _ => None,
+        })
+}
+
+fn pointer_get_mut<'a>(root: &'a mut Bson, pointer: &JsonPointer) -> Option<&'a mut Bson> {
+    pointer
+        .0
+        .iter()
+        .try_fold(root, |current, token| match current {
+            Bson::Document(document) => document.get_mut(token),
+            Bson::Array(array) => array.get_mut(parse_index(token)?),
+            _ => None,
+        })
+}
+
+fn add(root: &mut Bson, path: &JsonPointer, value: Bson) -> Result<(), PatchError> {
+    let Some((parent, last)) = path.parent() else {
+        *root = value;
+        return Ok(());
+    };
+    let not_found = || PatchError::NotFound(path.to_string());
+    match pointer_get_mut(root, &parent).ok_or_else(not_found)? {
+        Bson::Document(document) => {
+            document.insert(last, value);
+        }
+        Bson::Array(array) => {
+            let index = if last == "-" {
+                array.len()
+            } else {
+                parse_index(last)
+                    .filter(|index| *index <= array.len())
+                    .ok_or_else(not_found)?
+            };
+            array.insert(index, value);
+        }
+        _ => return Err(not_found()),
+    }
+    Ok(())
+}
+
+fn remove(root: &mut Bson, path: &JsonPointer) -> Result<Bson, PatchError> {
+    let not_found = || PatchError::NotFound(path.to_string());
+    let (parent, last) = path
+        .parent()
+        .ok_or_else(|| PatchError::Invalid("document root can not be removed".into()))?;
+    match pointer_get_mut(root, &parent).ok_or_else(not_found)? {
+        Bson::Document(document) => document.remove(last).ok_or_else(not_found),
+        Bson::Array(array) => parse_index(last)
+            .filter(|index| *index < array.len())
+            .map(|index| array.remove(index))
+            .ok_or_else(not_found),
+        _ => Err(not_found()),
+    }
+}
+
+fn apply_operation(root: &mut Bson, operation: &PatchOperation) -> Result<(), PatchError> {
+    match operation {
+        PatchOperation::Add { path, value } => add(root, path, value.clone()),
+        PatchOperation::Remove { path } => remove(root, path).map(drop),
+        PatchOperation::Replace { path, value } => {
+            let target = pointer_get_mut(root, path)
+                .ok_or_else(|| PatchError::NotFound(path.to_string()))?;
+            *target = value.clone();
+            Ok(())
+        }
+        PatchOperation::Move { from, path } => {
+            if from != path && from.overlaps(path) && from.0.len() < path.0.len() {
+                return Err(PatchError::Invalid(format!(
+                    "can not move `{from}` into one of its children"
+                )));
+            }
+            let value = remove(root, from)?;
+            add(root, path, value)
+        }
+        PatchOperation::Copy { from, path } => {
+            let value = pointer_get(root, from)
+                .ok_or_else(|| PatchError::NotFound(from.to_string()))?
+                .clone();
+            add(root, path, value)
+        }
+        PatchOperation::Test { path, value } => {
+            let found = pointer_get(root, path);
+            if found.is_some_and(|found| values_equal(found, value)) {
+                Ok(())
+            } else {
+                Err(PatchError::TestFailed(path.to_string()))
+            }
+        }
+    }
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(n) => Some(f64::from(*n)),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// Compares values with JSON semantics (numbers by value, objects regardless of key order).
+fn values_equal(left: &Bson, right: &Bson) -> bool {
+    match (left, right) {
+        (Bson::Document(left), Bson::Document(right)) => {
+            left.len() == right.len()
+                && left.iter().all(|(key, value)| {
+                    right
+                        .get(key)
+                        .is_some_and(|other| values_equal(value, other))
+                })
+        }
+        (Bson::Array(left), Bson::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| values_equal(l, r))
+        }
+        _ => match (as_number(left), as_number(right)) {
+            (Some(left), Some(right)) => left == right,
+            _ => left == right,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json_patch(operations: Bson) -> Patch {
+        let operations = mongodb::bson::deserialize_from_bson(operations).unwrap();
+        Patch::Json(operations)
+    }
+
+    mod json_pointer {
+        use super::*;
+
+        #[test]
+        fn invalid() {
+            assert!(JsonPointer::try_from("a/b".to_string()).is_err());
+        }
+
+        #[test]
+        fn unescaping() {
+            let pointer = JsonPointer::try_from("/a~1b/~01/".to_string()).unwrap();
+            assert_eq!(pointer.0, ["a/b", "~1", ""]);
+            assert_eq!(pointer.to_string(), "/a~1b/~01/");
+        }
+
+        #[test]
+        fn dotted() {
+            let pointer = JsonPointer::try_from("/a/0/b".to_string()).unwrap();
+            assert_eq!(pointer.dotted().unwrap(), "a.0.b");
+            let pointer = JsonPointer::try_from("/a.b".to_string()).unwrap();
+            assert!(pointer.dotted().is_none());
+            let pointer = JsonPointer::try_from(String::new()).unwrap();
+            assert!(pointer.dotted().is_none());
+        }
+    }
+
+    mod touched_fields {
+        use super::*;
+
+        #[test]
+        fn merge() {
+            let patch = Patch::Merge(doc! { "b": { "c": null }, "a": null });
+            let touched = patch.touched_fields();
+            let touched = touched.iter().collect::<Vec<_>>();
+            assert_eq!(
+                touched,
+                [
+                    ("patchAllowedFields", vec!["b"]),
+                    ("unsetAllowedFields", vec!["a"]),
+                ]
+            );
+        }
+
+        #[test]
+        fn json() {
+            let patch = json_patch(
+                vec![
+                    doc! { "op": "move", "from": "/a/b", "path": "/c" },
+                    doc! { "op": "test", "path": "/d", "value": 1 },
+                    doc! { "op": "copy", "from": "/e", "path": "/c/f" },
+                ]
+                .into(),
+            );
+            let touched = patch.touched_fields();
+            let touched = touched.iter().collect::<Vec<_>>();
+            assert_eq!(
+                touched,
+                [
+                    ("patchAllowedFields", vec!["c"]),
+                    ("unsetAllowedFields", vec!["a"]),
+                ]
+            );
+            let read = patch.read_paths();
+            let read = read.iter().map(ToString::to_string).collect::<Vec<_>>();
+            assert_eq!(read, ["/a/b", "/d", "/e"]);
+        }
+
+        #[test]
+        fn operators() {
+            let patch = Patch::from_object(HashMap::from([
+                ("$inc".into(), doc! { "a.b": 1, "c": 2 }.into()),
+                ("$addToSet".into(), doc! { "d": "x" }.into()),
+            ]))
+            .unwrap();
+            let touched = patch.touched_fields();
+            let touched = touched.iter().collect::<Vec<_>>();
+            assert_eq!(
+                touched,
+                [
+                    ("addToSetAllowedFields", vec!["d"]),
+                    ("incAllowedFields", vec!["a", "c"]),
+                ]
+            );
+        }
+    }
+
+    mod denied_by {
+        use super::*;
+
+        fn authorization() -> Document {
+            doc! {
+                "_id": "_authorization",
+                "patchAllowedFields": ["a"],
+                "roles": {
+                    "maintenance": { "patchAllowedFields": ["b"], "unsetAllowedFields": ["c"] },
+                    "operator": { "patchAllowedFields": ["d"] },
+                },
+            }
+        }
+
+        #[test]
+        fn everyone() {
+            let patch = Patch::Merge(doc! { "a": 1 });
+            assert!(
+                patch
+                    .touched_fields()
+                    .denied_by(&authorization(), &[])
+                    .is_empty()
+            );
+            let patch = Patch::Merge(doc! { "a": 1, "b": 2 });
+            assert_eq!(
+                patch.touched_fields().denied_by(&authorization(), &[]),
+                BTreeSet::from(["b".into()])
+            );
+        }
+
+        #[test]
+        fn roles() {
+            let patch = Patch::Merge(doc! { "a": 1, "b": 2, "c": null });
+            let touched = patch.touched_fields();
+            assert!(
+                touched
+                    .denied_by(&authorization(), &["maintenance".into()])
+                    .is_empty()
+            );
+            assert_eq!(
+                touched.denied_by(&authorization(), &["operator".into()]),
+                BTreeSet::from(["b".into(), "c".into()])
+            );
+            let patch = Patch::Merge(doc! { "b": 2, "d": 4 });
+            let roles = ["maintenance".into(), "operator".into(), "unknown".into()];
+            assert!(
+                patch
+                    .touched_fields()
+                    .denied_by(&authorization(), &roles)
+                    .is_empty()
+            );
+        }
+
+        #[test]
+        fn fields() {
+            let patch = Patch::Merge(doc! { "a": 1, "b": null });
+            assert_eq!(patch.touched_fields().fields(), BTreeSet::from(["a", "b"]));
+        }
+    }
+
+    mod from_object {
+        use super::*;
+
+        #[test]
+        fn fields() {
+            let patch = Patch::from_object(HashMap::from([("a".into(), Bson::Int32(1))])).unwrap();
+            assert!(matches!(patch, Patch::Fields(_)));
+        }
+
+        #[test]
+        fn mixed() {
+            let object = HashMap::from([
+                ("a".into(), Bson::Int32(1)),
+                ("$inc".into(), doc! { "b": 1 }.into()),
+            ]);
+            assert!(Patch::from_object(object).is_err());
+        }
+
+        #[test]
+        fn unsupported_operator() {
+            let object = HashMap::from([("$rename".into(), doc! { "a": "b" }.into())]);
+            assert!(Patch::from_object(object).is_err());
+        }
+
+        #[test]
+        fn not_an_object() {
+            let object = HashMap::from([("$inc".into(), Bson::Int32(1))]);
+            assert!(Patch::from_object(object).is_err());
+        }
+    }
+
+    mod atomic_update {
+        use super::*;
+
+        #[test]
+        fn merge() {
+            let patch = Patch::Merge(doc! { "a": null, "b": 1, "c": [{ "d": null }] });
+            let expected = AtomicUpdate {
+                preconditions: doc! {},
+                update: doc! { "$set": { "b": 1, "c": [{ "d": null }] }, "$unset": { "a": "" } },
+            };
+            assert_eq!(patch.atomic_update().unwrap(), expected);
+        }
+
+        #[test]
+        fn merge_objects() {
+            for changes in [doc! { "a": 1, "e": {} }, doc! { "b": { "c": 1 } }] {
+                assert!(Patch::Merge(changes).atomic_update().is_none());
+            }
+        }
+
+        #[test]
+        fn operators() {
+            let patch = Patch::from_object(HashMap::from([
+                ("$push".into(), doc! { "a": { "$each": [1, 2] } }.into()),
+                ("$max".into(), doc! { "b": 3 }.into()),
+                ("$pull".into(), doc! {}.into()),
+            ]))
+            .unwrap();
+            let expected = AtomicUpdate {
+                preconditions: doc! {},
+                update: doc! { "$max": { "b": 3 }, "$push": { "a": { "$each": [1, 2] } } },
+            };
+            assert_eq!(patch.atomic_update().unwrap(), expected);
+        }
+
+        #[test]
+        fn merge_dotted_key() {
+            let patch = Patch::Merge(doc! { "a.b": 1 });
+            assert!(patch.atomic_update().is_none());
+        }
+
+        #[test]
+        fn json() {
+            let patch = json_patch(
+                vec![
+                    doc! { "op": "test", "path": "/v", "value": 3 },
+                    doc! { "op": "add", "path": "/a/b", "value": 1 },
+                    doc! { "op": "replace", "path": "/c", "value": "x" },
+                    doc! { "op": "remove", "path": "/d" },
+                    doc! { "op": "add", "path": "/e/-", "value": 2 },
+                ]
+                .into(),
+            );
+            let expected = AtomicUpdate {
+                preconditions: doc! {
+                    "$and": [
+                        { "$expr": { "$eq": ["$v", { "$literal": 3 }] } },
+                        { "a": { "$type": "object" } },
+                        { "c": { "$exists": true } },
+                        { "d": { "$exists": true } },
+                        { "e": { "$type": "array" } },
+                    ],
+                },
+                update: doc! {
+                    "$set": { "a.b": 1, "c": "x" },
+                    "$unset": { "d": "" },
+                    "$push": { "e": 2 },
+                },
+            };
+            assert_eq!(patch.atomic_update().unwrap(), expected);
+        }
+
+        #[test]
+        fn json_not_atomic() {
+            let cases = [
+                doc! { "op": "add", "path": "/a/0", "value": 1 },
+                doc! { "op": "remove", "path": "/a/0" },
+                doc! { "op": "move", "from": "/a", "path": "/b" },
+                doc! { "op": "copy", "from": "/a", "path": "/b" },
+                doc! { "op": "replace", "path": "/a.b", "value": 1 },
+            ];
+            for case in cases {
+                let patch = json_patch(vec![case].into());
+                assert!(patch.atomic_update().is_none());
+            }
+        }
+
+        #[test]
+        fn json_overlapping_paths() {
+            let patch = json_patch(
+                vec![
+                    doc! { "op": "add", "path": "/a/b", "value": 1 },
+                    doc! { "op": "test", "path": "/a", "value": 1 },
+                ]
+                .into(),
+            );
+            assert!(patch.atomic_update().is_none());
+            let patch = json_patch(
+                vec![
+                    doc! { "op": "add", "path": "/a", "value": {} },
+                    doc! { "op": "add", "path": "/a/b", "value": 1 },
+                ]
+                .into(),
+            );
+            assert!(patch.atomic_update().is_none());
+        }
+    }
+
+    mod apply {
+        use super::*;
+
+        #[test]
+        fn merge() {
+            let mut document = doc! { "a": 1, "b": { "c": 2, "d": 3 }, "e": 4 };
+            let patch =
+                Patch::Merge(doc! { "a": null, "b": { "c": null, "f": 5 }, "e": { "g": 6 } });
+            patch.apply(&mut document).unwrap();
+            assert_eq!(document, doc! { "b": { "d": 3, "f": 5 }, "e": { "g": 6 } });
+        }
+
+        #[test]
+        fn merge_objects() {
+            let mut document = doc! { "a": 1, "b": { "c": 2 } };
+            let patch = Patch::Merge(doc! { "a": { "d": 3, "e": null }, "b": {}, "f": {} });
+            patch.apply(&mut document).unwrap();
+            assert_eq!(document, doc! { "a": { "d": 3 }, "b": { "c": 2 }, "f": {} });
+        }
+
+        #[test]
+        fn json() {
+            let mut document = doc! { "a": [1, 2, 3], "b": { "c": "x" } };
+            let patch = json_patch(
+                vec![
+                    doc! { "op": "add", "path": "/a/1", "value": 9 },
+                    doc! { "op": "remove", "path": "/a/0" },
+                    doc! { "op": "copy", "from": "/b/c", "path": "/d" },
+                    doc! { "op": "move", "from": "/b", "path": "/e" },
+                    doc! { "op": "replace", "path": "/a/2", "value": 7 },
+                    doc! { "op": "test", "path": "/a", "value": [9.0, 2, 7] },
+                ]
+                .into(),
+            );
+            patch.apply(&mut document).unwrap();
+            assert_eq!(
+                document,
+                doc! { "a": [9, 2, 7], "d": "x", "e": { "c": "x" } }
+            );
+        }
+
+        #[test]
+        fn json_errors() {
+            let cases = [
+                (
+                    doc! { "op": "test", "path": "/a", "value": 2 },
+                    PatchError::TestFailed("/a".into()),
+                ),
+                (
+                    doc! { "op": "replace", "path": "/b", "value": 2 },
+                    PatchError::NotFound("/b".into()),
+                ),
+                (
+                    doc! { "op": "add", "path": "/a/b", "value": 2 },
+                    PatchError::NotFound("/a/b".into()),
+                ),
+                (
+                    doc! { "op": "replace", "path": "", "value": 2 },
+                    PatchError::Invalid("document root must be an object".into()),
+                ),
+            ];
+            for (operation, expected) in cases {
+                let mut document = doc! { "a": 1 };
+                let patch = json_patch(vec![operation].into());
+                assert_eq!(patch.apply(&mut document).unwrap_err(), expected);
+            }
+        }
+
+        #[test]
+        fn empty_operators() {
+            let patch = Patch::from_object(HashMap::from([("$inc".into(), doc! {}.into())]));
+            let err = patch.unwrap().apply(&mut doc! { "a": 1 }).unwrap_err();
+            assert_eq!(
+                err,
+                PatchError::Invalid("update operators must not be empty".into())
+            );
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use axum::Json;
+use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use mongodb::bson::{Bson, Document};
+use serde::Serialize;
+
+const PROBLEM_CONTENT_TYPE: &str = "application/problem+json";
+
+/// Problem details (RFC 9457) response.
+#[derive(Debug, Serialize)]
+pub(crate) struct Problem {
+    #[serde(skip)]
+    status_code: StatusCode,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(flatten)]
+    extensions: Document,
+}
+
+impl Problem {
+    pub(crate) fn new(status_code: StatusCode, title: &'static str, detail: String) -> Self {
+        Self {
+            status_code,
+            title,
+            status: status_code.as_u16(),
+            detail,
+            extensions: Document::new(),
+        }
+    }
+
+    /// Adds an extension member to the problem details.
+    pub(crate) fn with(mut self, key: &str, value: impl Into<Bson>) -> Self {
+        self.extensions.insert(key, value);
+        self
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        (
+            self.status_code,
+            [(CONTENT_TYPE, PROBLEM_CONTENT_TYPE)],
+            Json(self),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn into_response() {
+        let problem = Problem::new(StatusCode::CONFLICT, "Some title", "some detail".into())
+            .with("some", vec!["a", "b"]);
+        let res = problem.into_response();
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(res.headers()["Content-Type"], "application/problem+json");
+        let body = to_bytes(res.into_body(), 1024).await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"title":"Some title","status":409,"detail":"some detail","some":["a","b"]}"#
+        );
+    }
+}
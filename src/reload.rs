@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use serde::Serialize;
+
+/// Value shared by the request handlers, replaced when the configuration is reloaded.
+#[derive(Debug, Default)]
+pub(crate) struct Reloadable<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T> From<T> for Reloadable<T> {
+    fn from(value: T) -> Self {
+        Self(Arc::new(RwLock::new(Arc::new(value))))
+    }
+}
+
+impl<T> Reloadable<T> {
+    /// Returns the current value, which requests keep using if it is replaced meanwhile.
+    pub(crate) fn get(&self) -> Arc<T> {
+        let current = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&current)
+    }
+
+    pub(crate) fn set(&self, value: T) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(value);
+    }
+}
+
+/// Counts of the configuration reloads, by outcome.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Reloads {
+    succeeded: Arc<AtomicU64>,
+    failed: Arc<AtomicU64>,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ReloadCounts {
+    succeeded: u64,
+    failed: u64,
+}
+
+impl Reloads {
+    pub(crate) fn record(&self, succeeded: bool) {
+        let count = if succeeded {
+            &self.succeeded
+        } else {
+            &self.failed
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn counts(&self) -> ReloadCounts {
+        ReloadCounts {
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reloadable() {
+        let reloadable = Reloadable::from(1);
+        let shared = reloadable.clone();
+        let before = shared.get();
+        reloadable.set(2);
+        assert_eq!(*before, 1);
+        assert_eq!(*shared.get(), 2);
+    }
+
+    #[test]
+    fn reloads() {
+        let reloads = Reloads::default();
+        reloads.record(true);
+        reloads.record(false);
+        reloads.record(true);
+        let counts = reloads.counts();
+        assert_eq!((counts.succeeded, counts.failed), (2, 1));
+    }
+}
@@ -0,0 +1,154 @@
+use jsonschema::Validator;
+use mongodb::bson::{Bson, Document, doc};
+
+/// Id of the reserved document holding the JSON Schema of the documents of a collection, in its
+/// `schema` field.
+pub(crate) const SCHEMA_ID: &str = "_schema";
+
+/// Ids of the reserved documents, which are not validated against the schema.
+const RESERVED_IDS: [&str; 2] = ["_authorization", SCHEMA_ID];
+
+/// Returns whether an id is the one of a reserved document, which patches and deletes can not
+/// write.
+pub(crate) fn is_reserved_id(id: &str) -> bool {
+    RESERVED_IDS.contains(&id)
+}
+
+/// Violation of a JSON Schema keyword by a document.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SchemaViolation {
+    /// JSON pointer to the invalid value.
+    pub(crate) path: String,
+    pub(crate) keyword: String,
+    pub(crate) message: String,
+}
+
+impl From<SchemaViolation> for Bson {
+    fn from(violation: SchemaViolation) -> Self {
+        doc! {
+            "path": violation.path,
+            "keyword": violation.keyword,
+            "message": violation.message,
+        }
+        .into()
+    }
+}
+
+/// Violations of the schema by a document.
+#[derive(Debug, PartialEq)]
+pub(crate) struct DocumentViolations {
+    pub(crate) id: Bson,
+    pub(crate) violations: Vec<SchemaViolation>,
+}
+
+impl From<DocumentViolations> for Bson {
+    fn from(document: DocumentViolations) -> Self {
+        doc! {
+            "id": document.id,
+            "violations": document.violations,
+        }
+        .into()
+    }
+}
+
+/// JSON Schema the documents of a collection must comply with.
+#[derive(Debug)]
+pub(crate) struct Schema(Validator);
+
+impl Schema {
+    pub(crate) fn compile(schema: &Document) -> Result<Self, String> {
+        let schema = serde_json::to_value(schema).map_err(|err| err.to_string())?;
+        jsonschema::validator_for(&schema)
+            .map(Self)
+            .map_err(|err| format!("invalid JSON Schema: {err}"))
+    }
+
+    /// Returns the violations of the schema by a document, sorted by path, reserved documents being
+    /// always valid. Their messages do not include the invalid values.
+    pub(crate) fn validate(&self, document: &Document) -> Vec<SchemaViolation> {
+        if let Ok(id) = document.get_str("_id")
+            && is_reserved_id(id)
+        {
+            return Vec::new();
+        }
+        let Ok(instance) = serde_json::to_value(document) else {
+            return Vec::new();
+        };
+        let mut violations = self
+            .0
+            .iter_errors(&instance)
+            .map(|err| SchemaViolation {
+                path: err.instance_path().to_string(),
+                keyword: err.kind().keyword().to_owned(),
+                message: err.masked().to_string(),
+            })
+            .collect::<Vec<_>>();
+        violations.sort_by(|a, b| (&a.path, &a.keyword).cmp(&(&b.path, &b.keyword)));
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Schema {
+        Schema::compile(&doc! {
+            "type": "object",
+            "properties": {
+                "limits": {
+                    "type": "object",
+                    "properties": { "temperature": { "type": "number", "maximum": 100 } },
+                },
+                "name": { "type": "string" },
+            },
+            "required": ["name"],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn invalid_schema() {
+        assert!(Schema::compile(&doc! { "type": "unknown" }).is_err());
+    }
+
+    #[test]
+    fn valid_document() {
+        let document = doc! { "_id": "a", "name": "n", "limits": { "temperature": 80 } };
+        assert!(schema().validate(&document).is_empty());
+    }
+
+    #[test]
+    fn violations() {
+        let document = doc! { "_id": "a", "limits": { "temperature": "80" } };
+        let violations = schema().validate(&document);
+        let violations = violations
+            .iter()
+            .map(|violation| (violation.path.as_str(), violation.keyword.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            [("", "required"), ("/limits/temperature", "type")]
+        );
+    }
+
+    #[test]
+    fn masked_values() {
+        let document = doc! { "_id": "a", "name": "hunter2", "limits": { "temperature": 101 } };
+        let messages = schema()
+            .validate(&document)
+            .into_iter()
+            .map(|violation| violation.message)
+            .collect::<Vec<_>>();
+        assert_eq!(messages, ["value is greater than the maximum of 100"]);
+        let document = doc! { "_id": "a", "name": ["hunter2"] };
+        let violations = schema().validate(&document);
+        assert!(!violations[0].message.contains("hunter2"), "{violations:?}");
+    }
+
+    #[test]
+    fn reserved_documents() {
+        let document = doc! { "_id": "_authorization", "patchAllowedFields": ["name"] };
+        assert!(schema().validate(&document).is_empty());
+    }
+}
@@ -0,0 +1,413 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, bail};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use clap::{Args, ValueEnum};
+use rustls::RootCertStore;
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{MissedTickBehavior, interval, sleep, timeout};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+use tracing::{Instrument, error, info, info_span, instrument, warn};
+use x509_parser::asn1_rs::{Oid, Tag, ToDer};
+use x509_parser::oid_registry::{
+    OID_DOMAIN_COMPONENT, OID_USERID, OID_X509_COMMON_NAME, OID_X509_COUNTRY_NAME,
+    OID_X509_LOCALITY_NAME, OID_X509_ORGANIZATION_NAME, OID_X509_ORGANIZATIONAL_UNIT,
+    OID_X509_STATE_OR_PROVINCE_NAME, OID_X509_STREET_ADDRESS,
+};
+use x509_parser::prelude::{AttributeTypeAndValue, FromDer, X509Certificate};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ClientAuth {
+    /// Refuse clients without a valid certificate
+    Required,
+    /// Accept clients without a certificate, still refusing invalid ones
+    Optional,
+}
+
+#[derive(Args)]
+#[group(id = "tls")]
+pub(crate) struct Config {
+    /// PEM file holding the server certificate chain, enabling TLS
+    #[arg(env, long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file holding the server private key
+    #[arg(env, long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM file holding the CA certificates verifying client certificates, enabling mutual TLS
+    #[arg(env, long, requires = "tls_cert")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Whether clients must give a certificate, when mutual TLS is enabled
+    #[arg(env, long, value_enum, default_value_t = ClientAuth::Required)]
+    tls_client_auth: ClientAuth,
+
+    /// Interval in seconds between checks of the certificate and key files for changes
+    #[arg(env, long, default_value_t = 30)]
+    tls_reload_interval: u64,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn load_certified_key(
+    cert_path: &Path,
+    key_path: &Path,
+    provider: &CryptoProvider,
+) -> anyhow::Result<CertifiedKey> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("error reading certificate `{}`", cert_path.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .with_context(|| format!("error reading private key `{}`", key_path.display()))?;
+    CertifiedKey::from_der(certs, key, provider)
+        .with_context(|| format!("error loading private key `{}`", key_path.display()))
+}
+
+/// Server certificate, replaced when its files change.
+#[derive(Debug)]
+struct CertificateResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+    fn replace(&self, certified_key: CertifiedKey) {
+        if let Ok(mut current) = self.current.write() {
+            *current = Arc::new(certified_key);
+        }
+    }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| Arc::clone(&current))
+    }
+}
+
+/// Server certificate in use, which can be reloaded from the files given by the configuration.
+#[derive(Clone)]
+pub(crate) struct ServerCertificate {
+    resolver: Arc<CertificateResolver>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertificate {
+    /// Loads the certificate and key files given by a configuration, to be then set.
+    pub(crate) fn load(&self, config: &Config) -> anyhow::Result<CertifiedKey> {
+        let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+            bail!("TLS cannot be disabled without a restart");
+        };
+        load_certified_key(cert_path, key_path, &self.provider)
+    }
+
+    pub(crate) fn set(&self, certified_key: CertifiedKey) {
+        self.resolver.replace(certified_key);
+    }
+}
+
+/// TLS settings of the HTTP server, if enabled.
+pub(crate) struct Tls {
+    acceptor: TlsAcceptor,
+    pub(crate) certificate: ServerCertificate,
+    /// Task reloading the certificate and key on change, to be aborted on shutdown.
+    pub(crate) reload_task: JoinHandle<()>,
+}
+
+impl Tls {
+    #[instrument(skip_all)]
+    pub(crate) async fn create(config: &Config) -> anyhow::Result<Option<Self>> {
+        let (Some(cert_path), Some(key_path)) = (&config.tls_cert, &config.tls_key) else {
+            info!(msg = "TLS disabled");
+            return Ok(None);
+        };
+        let provider = Arc::new(ring::default_provider());
+        let certified_key = load_certified_key(cert_path, key_path, &provider)?;
+        let resolver = Arc::new(CertificateResolver {
+            current: RwLock::new(Arc::new(certified_key)),
+        });
+        let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .context("error selecting TLS versions")?;
+        let builder = match &config.tls_client_ca {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_file_iter(ca_path)
+                    .with_context(|| format!("error reading CA bundle `{}`", ca_path.display()))?
+                {
+                    let cert = cert.with_context(|| {
+                        format!("error reading CA bundle `{}`", ca_path.display())
+                    })?;
+                    roots
+                        .add(cert)
+                        .with_context(|| format!("invalid CA in `{}`", ca_path.display()))?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider.clone());
+                let verifier = match config.tls_client_auth {
+                    ClientAuth::Required => verifier,
+                    ClientAuth::Optional => verifier.allow_unauthenticated(),
+                };
+                info!(msg = "mutual TLS enabled", ca = %ca_path.display());
+                builder.with_client_cert_verifier(verifier.build()?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_cert_resolver(resolver.clone());
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let reload_task = tokio::spawn(
+            reload_on_change(
+                resolver.clone(),
+                cert_path.clone(),
+                key_path.clone(),
+                provider.clone(),
+                Duration::from_secs(config.tls_reload_interval.max(1)),
+            )
+            .instrument(info_span!("tls_reload_task")),
+        );
+        info!(msg = "TLS enabled", cert = %cert_path.display());
+        Ok(Some(Self {
+            acceptor: TlsAcceptor::from(Arc::new(server_config)),
+            certificate: ServerCertificate { resolver, provider },
+            reload_task,
+        }))
+    }
+
+    pub(crate) fn listener(&self, tcp: TcpListener) -> TlsListener {
+        TlsListener {
+            tcp,
+            acceptor: self.acceptor.clone(),
+            handshakes: JoinSet::new(),
+        }
+    }
+}
+
+/// Polls the certificate and key files, reloading them when modified.
+async fn reload_on_change(
+    resolver: Arc<CertificateResolver>,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    provider: Arc<CryptoProvider>,
+    period: Duration,
+) {
+    info!(status = "started");
+    let mut last_modified = (modified(&cert_path), modified(&key_path));
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        let current_modified = (modified(&cert_path), modified(&key_path));
+        if current_modified == last_modified {
+            continue;
+        }
+        last_modified = current_modified;
+        match load_certified_key(&cert_path, &key_path, &provider) {
+            Ok(certified_key) => {
+                resolver.replace(certified_key);
+                info!(msg = "TLS certificate reloaded", cert = %cert_path.display());
+            }
+            Err(err) => {
+                warn!(
+                    msg = "keeping previous TLS certificate",
+                    err = format!("{err:#}")
+                );
+            }
+        }
+    }
+}
+
+/// TCP listener performing TLS handshakes concurrently, so that a slow client does not block
+/// the others.
+pub(crate) struct TlsListener {
+    tcp: TcpListener,
+    acceptor: TlsAcceptor,
+    handshakes: JoinSet<Option<(TlsStream<TcpStream>, SocketAddr)>>,
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            tokio::select! {
+                accepted = self.tcp.accept() => match accepted {
+                    Ok((stream, addr)) => {
+                        let acceptor = self.acceptor.clone();
+                        self.handshakes.spawn(async move {
+                            match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => Some((stream, addr)),
+                                Ok(Err(err)) => {
+                                    warn!(msg = "TLS handshake failed", %addr, %err);
+                                    None
+                                }
+                                Err(_) => {
+                                    warn!(msg = "TLS handshake timed out", %addr);
+                                    None
+                                }
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        error!(kind = "TCP accept", %err);
+                        sleep(Duration::from_secs(1)).await;
+                    }
+                },
+                Some(joined) = self.handshakes.join_next() => match joined {
+                    Ok(Some(accepted)) => return accepted,
+                    Ok(None) => {}
+                    Err(err) => error!(kind = "TLS handshake task", %err),
+                },
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        self.tcp.local_addr()
+    }
+}
+
+/// Short names of the usual distinguished name attributes (RFC 4514).
+const ATTRIBUTE_NAMES: [(&Oid, &str); 9] = [
+    (&OID_X509_COMMON_NAME, "CN"),
+    (&OID_X509_COUNTRY_NAME, "C"),
+    (&OID_X509_LOCALITY_NAME, "L"),
+    (&OID_X509_STATE_OR_PROVINCE_NAME, "ST"),
+    (&OID_X509_STREET_ADDRESS, "STREET"),
+    (&OID_X509_ORGANIZATION_NAME, "O"),
+    (&OID_X509_ORGANIZATIONAL_UNIT, "OU"),
+    (&OID_USERID, "UID"),
+    (&OID_DOMAIN_COMPONENT, "DC"),
+];
+
+/// Escapes an attribute value (RFC 4514 section 2.4).
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (index, character) in value.chars().enumerate() {
+        let special = matches!(character, '"' | '+' | ',' | ';' | '<' | '>' | '\\')
+            || (index == 0 && matches!(character, '#' | ' '))
+            || (index == last && character == ' ');
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(character);
+    }
+    escaped
+}
+
+/// Formats an attribute type and value.
+fn attribute(attribute: &AttributeTypeAndValue) -> Option<String> {
+    let name = ATTRIBUTE_NAMES
+        .iter()
+        .find(|(oid, _)| *oid == attribute.attr_type())
+        .map(|(_, name)| *name);
+    let value = attribute.attr_value();
+    let text = if matches!(
+        value.tag(),
+        Tag::Utf8String | Tag::PrintableString | Tag::TeletexString | Tag::Ia5String
+    ) {
+        std::str::from_utf8(value.data).ok()
+    } else {
+        None
+    };
+    Some(match (name, text) {
+        (Some(name), Some(text)) => format!("{name}={}", escape_value(text)),
+        _ => {
+            let name = name.map_or_else(|| attribute.attr_type().to_id_string(), str::to_owned);
+            format!("{name}=#{}", hex::encode(value.to_der_vec().ok()?))
+        }
+    })
+}
+
+/// Formats the subject distinguished name of a DER-encoded X.509 certificate (RFC 4514).
+fn certificate_subject(certificate: &[u8]) -> Option<String> {
+    let (_, certificate) = X509Certificate::from_der(certificate).ok()?;
+    let mut rdns = Vec::new();
+    for rdn in certificate.subject().iter() {
+        let mut attributes = rdn.iter().map(attribute).collect::<Option<Vec<_>>>()?;
+        attributes.reverse();
+        rdns.push(attributes.join("+"));
+    }
+    // The most specific name comes first, as printed by OpenSSL with the `RFC2253` option.
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+/// Client of a TLS connection.
+#[derive(Debug, Clone)]
+pub(crate) struct TlsClient {
+    pub(crate) addr: SocketAddr,
+    /// Subject of the verified certificate given by the client, if any.
+    pub(crate) certificate_subject: Option<String>,
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for TlsClient {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let (_, connection) = stream.io().get_ref();
+        let certificate_subject = connection
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|cert| certificate_subject(cert));
+        Self {
+            addr: *stream.remote_addr(),
+            certificate_subject,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed certificate of `C=FR, O=Plant\, Inc., OU=Line 1+OU=Robots, CN=robot #one`.
+    const CERTIFICATE: &str = concat!(
+        "30820206308201aba00302010202147b6aedede0364d36a7ffe9bbe77f788b7f5ccb1c300a06082a8648",
+        "ce3d0403023058310b300906035504061302465231143012060355040a0c0b506c616e742c20496e632e",
+        "311e300d060355040b0c064c696e652031300d060355040b0c06526f626f74733113301106035504030c",
+        "0a726f626f7420236f6e65301e170d3236313031393036353030355a170d333631303136303635303035",
+        "5a3058310b300906035504061302465231143012060355040a0c0b506c616e742c20496e632e311e300d",
+        "060355040b0c064c696e652031300d060355040b0c06526f626f74733113301106035504030c0a726f62",
+        "6f7420236f6e653059301306072a8648ce3d020106082a8648ce3d03010703420004541b326864383e13",
+        "ba99da676d532a030f388da5b0908b782cc9d789f2905bad9cac6efdd1b86ab8128eb26465349b6be314",
+        "514e76a11bfe6a5b0565521b4009a3533051301d0603551d0e0416041492aae6927f3ddb19b46078d0e4",
+        "b14e03a2561bff301f0603551d2304183016801492aae6927f3ddb19b46078d0e4b14e03a2561bff300f",
+        "0603551d130101ff040530030101ff300a06082a8648ce3d0403020349003046022100f70a2990a89787",
+        "916bbd00949a1d1769e83a7d48a2b444d42d28113b1f0c6527022100d99afe3fd85981e3a48d087e2471",
+        "16675590c2f0a5096bcc44b9af985981726c",
+    );
+
+    #[test]
+    fn escape() {
+        assert_eq!(escape_value("#a, b "), "\\#a\\, b\\ ");
+    }
+
+    #[test]
+    fn subject() {
+        let certificate = hex::decode(CERTIFICATE).unwrap();
+        let subject = certificate_subject(&certificate).unwrap();
+        assert_eq!(
+            subject,
+            "CN=robot #one,OU=Robots+OU=Line 1,O=Plant\\, Inc.,C=FR"
+        );
+        assert!(certificate_subject(&[0x30, 0x00]).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+//! W3C trace context (<https://www.w3.org/TR/trace-context/>) of the HTTP requests.
+
+use std::fmt;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+const HEADER_LENGTH: usize = 55;
+
+/// Trace and span (parent) IDs, as carried by `traceparent` headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TraceContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    flags: u8,
+}
+
+fn random<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random generator failure");
+    bytes
+}
+
+fn parse_hex<const N: usize>(hex: &str) -> Option<[u8; N]> {
+    // Only lowercase hexadecimal digits are valid.
+    if hex.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    hex::decode_to_slice(hex, &mut bytes).ok()?;
+    Some(bytes)
+}
+
+impl TraceContext {
+    /// Parses a `traceparent` header, returning `None` if it is not valid.
+    pub(crate) fn parse(header: &str) -> Option<Self> {
+        let version = parse_hex::<1>(header.get(..2)?)?[0];
+        let valid_length = match version {
+            0 => header.len() == HEADER_LENGTH,
+            0xff => false,
+            // Later versions may append fields.
+            _ => {
+                header.len() == HEADER_LENGTH
+                    || header.get(HEADER_LENGTH..=HEADER_LENGTH) == Some("-")
+            }
+        };
+        let separated = [2, 35, 52]
+            .into_iter()
+            .all(|index| header.get(index..=index) == Some("-"));
+        if !valid_length || !separated {
+            return None;
+        }
+        let trace_id = parse_hex(header.get(3..35)?)?;
+        let span_id = parse_hex(header.get(36..52)?)?;
+        let flags = parse_hex::<1>(header.get(53..55)?)?[0];
+        if trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            span_id,
+            flags,
+        })
+    }
+
+    /// Starts a trace, sampled.
+    pub(crate) fn root() -> Self {
+        Self {
+            trace_id: random(),
+            span_id: random(),
+            flags: 1,
+        }
+    }
+
+    /// Returns the context of a new span of the trace.
+    pub(crate) fn child(&self) -> Self {
+        Self {
+            span_id: random(),
+            ..*self
+        }
+    }
+
+    pub(crate) fn trace_id(&self) -> String {
+        hex::encode(self.trace_id)
+    }
+
+    pub(crate) fn span_id(&self) -> String {
+        hex::encode(self.span_id)
+    }
+}
+
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{}-{}-{:02x}",
+            self.trace_id(),
+            self.span_id(),
+            self.flags
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn parse() {
+        let context = TraceContext::parse(HEADER).unwrap();
+        assert_eq!(context.trace_id(), "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id(), "00f067aa0ba902b7");
+        assert_eq!(context.to_string(), HEADER);
+        let later = "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-extra";
+        assert!(TraceContext::parse(later).is_some());
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00_4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-0g",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn child() {
+        let context = TraceContext::parse(HEADER).unwrap();
+        let child = context.child();
+        assert_eq!(child.trace_id(), context.trace_id());
+        assert_ne!(child.span_id(), context.span_id());
+        assert!(child.to_string().ends_with("-01"));
+        let root = TraceContext::root();
+        assert_eq!(TraceContext::parse(&root.to_string()), Some(root));
+    }
+}